@@ -1,7 +1,7 @@
 use chrono::{
     naive::NaiveDate,
     offset::{TimeZone, Utc},
-    DateTime,
+    DateTime, Duration,
 };
 use ckb_types::core::EpochNumberWithFraction;
 use failure::Error;
@@ -9,6 +9,7 @@ use failure::Error;
 const EPOCH_DURATION: u64 = 4 * 60 * 60;
 const EPOCH_LENGTH: u64 = 1_800;
 const SINCE_FLAG: u64 = 0x2000_0000_0000_0000;
+const SINCE_FLAG_MASK: u64 = 0xff00_0000_0000_0000;
 
 pub fn parse_date(input: &str) -> Result<DateTime<Utc>, Error> {
     let date = NaiveDate::parse_from_str(input, "%Y-%m-%d")?.and_hms(0, 0, 0);
@@ -18,8 +19,12 @@ pub fn parse_date(input: &str) -> Result<DateTime<Utc>, Error> {
 pub struct Outset;
 
 impl Outset {
+    pub fn start(&self) -> DateTime<Utc> {
+        Utc.ymd(2019, 11, 16).and_hms(6, 0, 0)
+    }
+
     pub fn since(&self, date: &DateTime<Utc>) -> u64 {
-        (date.timestamp() - Utc.ymd(2019, 11, 16).and_hms(6, 0, 0).timestamp()) as u64
+        (date.timestamp() - self.start().timestamp()) as u64
     }
 
     pub fn since_epoch(&self, date: &DateTime<Utc>, target: u64) -> u64 {
@@ -34,12 +39,134 @@ impl Outset {
         };
         EpochNumberWithFraction::new(epoch, index, EPOCH_LENGTH).full_value() + SINCE_FLAG
     }
+
+    /// Projects a since produced by `since_epoch` back to the lock time.
+    ///
+    /// The epoch index is rounded down to 8 seconds, so the result may be a few
+    /// seconds before the original date. Returns `None` for a clamped since.
+    pub fn date_of_since(&self, since: u64, target: u64) -> Option<DateTime<Utc>> {
+        let epoch = EpochNumberWithFraction::from_full_value(since & !SINCE_FLAG_MASK);
+        if epoch.number() == 0 && epoch.index() == 0 {
+            return None;
+        }
+        let epochs = (epoch.number() + target).checked_sub(89)?;
+        let seconds = epochs * EPOCH_DURATION + epoch.index() * EPOCH_DURATION / epoch.length();
+        Some(self.start() + Duration::seconds(seconds as i64))
+    }
+}
+
+/// Splits a since into its flag byte and the epoch.
+pub fn decode_since(since: u64) -> (u8, EpochNumberWithFraction) {
+    (
+        (since >> 56) as u8,
+        EpochNumberWithFraction::from_full_value(since & !SINCE_FLAG_MASK),
+    )
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const SECONDS_PER_DAY: i64 = 24 * 60 * 60;
+
+    fn decode(since: u64) -> (u64, EpochNumberWithFraction) {
+        let (flag, epoch) = decode_since(since);
+        (u64::from(flag) << 56, epoch)
+    }
+
+    /// Every day from the outset for ten years.
+    fn dates() -> impl Iterator<Item = DateTime<Utc>> {
+        let first = Utc.ymd(2019, 11, 17).and_hms(0, 0, 0);
+        (0..3650).map(move |day| first + Duration::seconds(day * SECONDS_PER_DAY))
+    }
+
+    #[test]
+    fn test_since_epoch_fields_every_day() {
+        for target in (4..=200).step_by(7) {
+            for date in dates() {
+                let since = Outset.since_epoch(&date, target);
+                let (flag, epoch) = decode(since);
+                assert_eq!(flag, SINCE_FLAG, "{} {}", date, target);
+                assert_eq!(epoch.length(), EPOCH_LENGTH);
+                assert!(epoch.index() < EPOCH_LENGTH);
+                assert_eq!(
+                    EpochNumberWithFraction::new(epoch.number(), epoch.index(), epoch.length())
+                        .full_value()
+                        + SINCE_FLAG,
+                    since
+                );
+
+                let elapsed = Outset.since(&date);
+                let offset = elapsed / EPOCH_DURATION + 89;
+                if offset >= target {
+                    assert_eq!(epoch.number(), offset - target, "{} {}", date, target);
+                    assert_eq!(
+                        epoch.index(),
+                        (elapsed % EPOCH_DURATION) * EPOCH_LENGTH / EPOCH_DURATION
+                    );
+                } else {
+                    // negative epoch is clamped to the start of epoch 0
+                    assert_eq!(
+                        (epoch.number(), epoch.index()),
+                        (0, 0),
+                        "{} {}",
+                        date,
+                        target
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_date_of_since_every_day() {
+        for target in (4..=200).step_by(7) {
+            for date in dates() {
+                let since = Outset.since_epoch(&date, target);
+                match Outset.date_of_since(since, target) {
+                    Some(projected) => {
+                        assert!(projected <= date, "{} {}", date, target);
+                        assert!(
+                            date - projected < Duration::seconds(8),
+                            "{} {}",
+                            date,
+                            target
+                        );
+                    }
+                    None => assert!(Outset.since(&date) / EPOCH_DURATION + 89 <= target),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_since_epoch_monotonic_every_day() {
+        for target in (4..=200).step_by(7) {
+            let mut last = (0, 0);
+            for date in dates() {
+                let (_, epoch) = decode(Outset.since_epoch(&date, target));
+                let current = (epoch.number(), epoch.index());
+                assert!(current >= last, "{} {}", date, target);
+                last = current;
+            }
+        }
+    }
+
+    #[test]
+    fn test_since_epoch_target_shift_every_day() {
+        // the lock moves one epoch earlier for each epoch the launch is delayed
+        for date in dates() {
+            for target in 4..200 {
+                let (_, current) = decode(Outset.since_epoch(&date, target));
+                let (_, delayed) = decode(Outset.since_epoch(&date, target + 1));
+                if current.number() > 0 {
+                    assert_eq!(delayed.number() + 1, current.number());
+                    assert_eq!(delayed.index(), current.index());
+                }
+            }
+        }
+    }
+
     #[test]
     fn test_parse_date() {
         let dt = parse_date("2020-01-01");
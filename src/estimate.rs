@@ -0,0 +1,215 @@
+use chrono::{prelude::*, Duration};
+use ckb_types::core::HeaderView;
+use failure::Error;
+use serde_derive::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Estimate {
+    pub target: u64,
+    /// Index in epoch `target + 1` of the block lina waits for.
+    pub ready_index: u64,
+    pub ready: bool,
+    pub tip_number: u64,
+    pub tip_epoch: u64,
+    pub tip_epoch_index: u64,
+    pub tip_epoch_length: u64,
+    pub window: u64,
+    pub avg_epoch_duration: u64,
+    pub epoch_duration_stddev: u64,
+    pub remaining_seconds: u64,
+    pub earliest_remaining_seconds: u64,
+    pub latest_remaining_seconds: u64,
+    pub launch_time_utc: String,
+    pub launch_time_local: String,
+    pub earliest_launch_time_utc: String,
+    pub latest_launch_time_utc: String,
+}
+
+impl Estimate {
+    /// `remaining_epochs` is the fractional number of epochs until lina is ready,
+    /// `durations` are the elapsed seconds of the sampled full epochs.
+    pub fn new(
+        target: u64,
+        ready_index: u64,
+        tip_header: &HeaderView,
+        remaining_epochs: f64,
+        durations: &[u64],
+        default_duration: u64,
+    ) -> Estimate {
+        let tip_epoch = tip_header.epoch();
+        let (avg, stddev) = if durations.is_empty() {
+            (default_duration as f64, 0f64)
+        } else {
+            let n = durations.len() as f64;
+            let avg = durations.iter().sum::<u64>() as f64 / n;
+            let variance = durations
+                .iter()
+                .map(|d| (*d as f64 - avg).powi(2))
+                .sum::<f64>()
+                / n;
+            (avg, variance.sqrt())
+        };
+
+        // epochs are treated as independent samples, the spread of the sum grows
+        // with the square root of the remaining epochs, bounds are two sigma.
+        let remaining = remaining_epochs * avg;
+        let spread = 2.0 * stddev * remaining_epochs.sqrt();
+        let earliest = (remaining - spread).max(0.0);
+        let latest = remaining + spread;
+
+        let now = Utc::now();
+        let at = |seconds: f64| now + Duration::seconds(seconds as i64);
+
+        Estimate {
+            target,
+            ready_index,
+            ready: remaining_epochs <= 0.0,
+            tip_number: tip_header.number(),
+            tip_epoch: tip_epoch.number(),
+            tip_epoch_index: tip_epoch.index(),
+            tip_epoch_length: tip_epoch.length(),
+            window: durations.len() as u64,
+            avg_epoch_duration: avg as u64,
+            epoch_duration_stddev: stddev as u64,
+            remaining_seconds: remaining as u64,
+            earliest_remaining_seconds: earliest as u64,
+            latest_remaining_seconds: latest as u64,
+            launch_time_utc: at(remaining).to_rfc3339(),
+            launch_time_local: at(remaining).with_timezone(&Local).to_rfc3339(),
+            earliest_launch_time_utc: at(earliest).to_rfc3339(),
+            latest_launch_time_utc: at(latest).to_rfc3339(),
+        }
+    }
+
+    pub fn print(&self) {
+        if self.ready {
+            println!("Lina is ready, epoch {} is finalized.", self.target);
+            return;
+        }
+        println!(
+            "Lina is not ready yet. Please wait for the block at index {} in epoch {}.",
+            self.ready_index,
+            self.target + 1
+        );
+        println!(
+            "Tip: block {}, epoch {} ({}/{})",
+            self.tip_number, self.tip_epoch, self.tip_epoch_index, self.tip_epoch_length
+        );
+        println!(
+            "Estimated remaining time: {}",
+            format_duration(self.remaining_seconds)
+        );
+        println!(
+            "Confidence range: {} ~ {}",
+            format_duration(self.earliest_remaining_seconds),
+            format_duration(self.latest_remaining_seconds)
+        );
+        println!("Estimated launch time (UTC): {}", self.launch_time_utc);
+        println!("Estimated launch time (local): {}", self.launch_time_local);
+        println!(
+            "Launch window (UTC): {} ~ {}",
+            self.earliest_launch_time_utc, self.latest_launch_time_utc
+        );
+    }
+
+    pub fn print_json(&self) -> Result<(), Error> {
+        println!("{}", serde_json::to_string_pretty(self)?);
+        Ok(())
+    }
+}
+
+pub fn format_duration(seconds: u64) -> String {
+    let mut formatted = String::new();
+    if seconds >= 86400 {
+        formatted.push_str(&format!("{} days ", seconds / 86400));
+    }
+    formatted.push_str(&format!(
+        "{:02}h{:02}m{:02}s",
+        seconds / 3600 % 24,
+        seconds / 60 % 60,
+        seconds % 60
+    ));
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ckb_types::{
+        core::{EpochNumberWithFraction, HeaderBuilder},
+        prelude::*,
+    };
+
+    fn tip_header() -> HeaderView {
+        HeaderBuilder::default()
+            .number(103u64.pack())
+            .epoch(EpochNumberWithFraction::new(5, 3, 20).full_value().pack())
+            .build()
+    }
+
+    #[test]
+    fn test_confidence_bounds() {
+        let estimate = Estimate::new(4, 10, &tip_header(), 4.0, &[100, 200, 300], 14_400);
+        assert!(!estimate.ready);
+        assert_eq!(estimate.window, 3);
+        assert_eq!(estimate.avg_epoch_duration, 200);
+        // sqrt(20000 / 3)
+        assert_eq!(estimate.epoch_duration_stddev, 81);
+        assert_eq!(estimate.remaining_seconds, 800);
+        // 800 -+ 2 * 81.65 * sqrt(4)
+        assert_eq!(estimate.earliest_remaining_seconds, 473);
+        assert_eq!(estimate.latest_remaining_seconds, 1126);
+        assert_eq!(
+            (
+                estimate.tip_number,
+                estimate.tip_epoch,
+                estimate.tip_epoch_index
+            ),
+            (103, 5, 3)
+        );
+    }
+
+    #[test]
+    fn test_default_duration() {
+        let estimate = Estimate::new(4, 10, &tip_header(), 0.5, &[], 14_400);
+        assert_eq!(estimate.window, 0);
+        assert_eq!(estimate.avg_epoch_duration, 14_400);
+        assert_eq!(estimate.remaining_seconds, 7_200);
+        assert_eq!(estimate.earliest_remaining_seconds, 7_200);
+        assert_eq!(estimate.latest_remaining_seconds, 7_200);
+    }
+
+    #[test]
+    fn test_ready() {
+        let estimate = Estimate::new(4, 10, &tip_header(), 0.0, &[100, 200, 300], 14_400);
+        assert!(estimate.ready);
+        assert_eq!(estimate.remaining_seconds, 0);
+        assert_eq!(estimate.earliest_remaining_seconds, 0);
+        assert_eq!(estimate.latest_remaining_seconds, 0);
+    }
+
+    #[test]
+    fn test_json() {
+        let estimate = Estimate::new(4, 10, &tip_header(), 4.0, &[100, 200, 300], 14_400);
+        let json = serde_json::to_value(&estimate).unwrap();
+        assert_eq!(json["ready_index"], 10);
+        assert_eq!(json["remaining_seconds"], 800);
+        assert_eq!(json["earliest_remaining_seconds"], 473);
+        assert_eq!(json["latest_remaining_seconds"], 1126);
+        let launch = DateTime::parse_from_rfc3339(json["launch_time_utc"].as_str().unwrap());
+        let earliest =
+            DateTime::parse_from_rfc3339(json["earliest_launch_time_utc"].as_str().unwrap());
+        assert_eq!((launch.unwrap() - earliest.unwrap()).num_seconds(), 327);
+    }
+
+    #[test]
+    fn test_format_duration() {
+        assert_eq!(format_duration(0), "00h00m00s");
+        assert_eq!(format_duration(59), "00h00m59s");
+        assert_eq!(format_duration(3_599), "00h59m59s");
+        assert_eq!(format_duration(3_600), "01h00m00s");
+        assert_eq!(format_duration(86_399), "23h59m59s");
+        assert_eq!(format_duration(86_400), "1 days 00h00m00s");
+        assert_eq!(format_duration(2 * 86_400 + 3_661), "2 days 01h01m01s");
+    }
+}
@@ -1,241 +1,1431 @@
 use crate::address::Address;
-use crate::output::Output;
+use crate::estimate::{format_duration, Estimate};
+use crate::output::{Audit, Output};
 use crate::rpc::RpcClient;
-use crate::DEFAULT_CODE_HASH;
-use chrono::{prelude::*, Duration};
-use ckb_rational::RationalU256;
+use crate::source::ChainSource;
+use crate::{BYTE_SHANNONS, DEFAULT_CODE_HASH, INCENTIVES_ADDRESS};
+use ckb_jsonrpc_types::Script as JsonScript;
 use ckb_types::{
     bytes::Bytes,
-    core::{capacity_bytes, BlockView, Capacity, HeaderView},
-    packed::{Byte32, CellbaseWitness},
+    core::{capacity_bytes, BlockView, Capacity, HeaderView, ScriptHashType, TransactionView},
+    packed::{Byte32, CellbaseWitness, Script, Transaction},
     prelude::*,
-    utilities::{compact_to_difficulty, difficulty_to_compact},
-    U256,
+    utilities::{compact_to_difficulty, difficulty_to_compact, merkle_root},
+    H256, U256,
 };
-use failure::Error;
+use failure::{format_err, Error, Fail};
 use indicatif::{ProgressBar, ProgressStyle};
+use serde_derive::{Deserialize, Serialize};
+use std::cell::Cell;
 use std::collections::{BTreeMap, HashMap, VecDeque};
-use std::fs::File;
+use std::fmt;
 use std::ops::Add;
 use std::process::exit;
+use std::str::FromStr;
+use std::thread::sleep;
+use std::time::Duration;
 
 const TOTAL_REWARD: Capacity = capacity_bytes!(18_000_000);
 const THRESHOLD: Capacity = capacity_bytes!(1_000);
-const METRIC_EPOCH: u64 = 4;
-const BYTE_SHANNONS: u64 = 100_000_000;
+pub const METRIC_EPOCH: u64 = 4;
+const DEFAULT_EPOCH_DURATION: u64 = 4 * 3600;
+// primary issuance schedule in ckb consensus
+const INITIAL_PRIMARY_EPOCH_REWARD: Capacity = Capacity::shannons(1_917_808_21917808);
+const PRIMARY_EPOCH_REWARD_HALVING_INTERVAL: u64 = 4 * 365 * 6;
+
+/// Upper bound of the finalization delay inferred from the chain.
+pub const MAX_FINALIZATION_DELAY: u64 = 1_000;
+
+/// Compare block rewards with peers every this many blocks by default.
+pub const DEFAULT_CROSS_CHECK_INTERVAL: u64 = 1_000;
+
+const MIN_POLL_INTERVAL: u64 = 5;
+const MAX_POLL_INTERVAL: u64 = 10 * 60;
+
+/// How much the explorer verifies what it reads from the nodes.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// Extra blocks required past the scanned range.
+    pub confirmations: u64,
+    /// Compare block rewards with peers every `cross_check_interval` blocks.
+    pub cross_check_interval: u64,
+    /// Recompute the primary reward of every block from the issuance schedule
+    /// instead of trusting `get_cellbase_output_capacity_details`.
+    pub verify_rewards: bool,
+    /// Blocks between a block and the cellbase paying its reward, inferred
+    /// from the first cellbase with outputs when `None`.
+    pub finalization_delay: Option<u64>,
+    /// The genesis hash the source chain must have. Without it a single
+    /// endpoint can serve a fabricated but consistent chain, only peers can
+    /// catch it.
+    pub genesis_hash: Option<H256>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            confirmations: 0,
+            cross_check_interval: DEFAULT_CROSS_CHECK_INTERVAL,
+            verify_rewards: false,
+            finalization_delay: None,
+            genesis_hash: None,
+        }
+    }
+}
 
 pub struct Explorer {
-    rpc: RpcClient,
+    source: Box<dyn ChainSource>,
+    /// Additional sources the results are cross-checked against.
+    peers: Vec<Box<dyn ChainSource>>,
     target: u64,
+    options: Options,
+    finalization_delay: Cell<Option<u64>>,
 }
 
 impl Explorer {
-    pub fn new(url: &str, target: u64) -> Explorer {
+    pub fn new(urls: &[&str], target: u64, options: Options) -> Explorer {
+        let mut clients = urls
+            .iter()
+            .map(|url| Box::new(RpcClient::new(url)) as Box<dyn ChainSource>);
+        let source = clients.next().expect("at least one url");
+        Explorer::with_sources(source, clients.collect(), target, options)
+    }
+
+    pub fn with_sources(
+        source: Box<dyn ChainSource>,
+        peers: Vec<Box<dyn ChainSource>>,
+        target: u64,
+        options: Options,
+    ) -> Explorer {
         Explorer {
-            rpc: RpcClient::new(url),
+            source,
+            peers,
             target,
+            finalization_delay: Cell::new(options.finalization_delay),
+            options: Options {
+                cross_check_interval: options.cross_check_interval.max(1),
+                ..options
+            },
+        }
+    }
+
+    /// Names of the source and then the peers.
+    pub fn source_names(&self) -> Vec<String> {
+        std::iter::once(&self.source)
+            .chain(&self.peers)
+            .map(|source| source.name())
+            .collect()
+    }
+
+    /// Asserts that every peer returns `expected` for `fetch`.
+    fn cross_check<T, F>(&self, what: &str, expected: &T, fetch: F) -> Result<(), Error>
+    where
+        T: PartialEq + fmt::Debug,
+        F: Fn(&dyn ChainSource) -> Result<T, Error>,
+    {
+        for peer in &self.peers {
+            let got = fetch(peer.as_ref())?;
+            if &got != expected {
+                return Err(DisagreementError {
+                    url: peer.name(),
+                    reason: format!("{}: expected {:?}, got {:?}", what, expected, got),
+                }
+                .into());
+            }
+        }
+        Ok(())
+    }
+
+    fn cross_check_block_hash(&self, number: u64, expected: &Byte32) -> Result<(), Error> {
+        let expected: H256 = expected.unpack();
+        self.cross_check(&format!("block {} hash", number), &Some(expected), |peer| {
+            peer.get_block_hash(number.into())
+        })
+    }
+
+    /// Peers may be a few blocks apart at their tips, so rather than the tips
+    /// themselves every peer must have reached `required` and agree on its hash.
+    fn cross_check_tip(&self, required: u64) -> Result<(), Error> {
+        if self.peers.is_empty() {
+            return Ok(());
+        }
+        for peer in &self.peers {
+            let tip: u64 = peer.get_tip_header()?.inner.number.into();
+            if tip < required {
+                return Err(DisagreementError {
+                    url: peer.name(),
+                    reason: format!("tip {} is below the required tip {}", tip, required),
+                }
+                .into());
+            }
+        }
+        let expected = self.source.get_block_hash(required.into())?;
+        self.cross_check(&format!("block {} hash", required), &expected, |peer| {
+            peer.get_block_hash(required.into())
+        })
+    }
+
+    /// The finalization delay of the source chain. Cellbases of the blocks
+    /// within the delay have no outputs, the first output rewards block 1.
+    pub fn finalization_delay(&self) -> Result<u64, Error> {
+        if let Some(delay) = self.finalization_delay.get() {
+            return Ok(delay);
+        }
+        for number in 1..=(MAX_FINALIZATION_DELAY + 1) {
+            let block: BlockView = self
+                .source
+                .get_block_by_number(number.into())?
+                .ok_or_else(|| {
+                    format_err!(
+                        "block {} not found while inferring the finalization delay",
+                        number
+                    )
+                })?
+                .into();
+            if pays_reward(&block) {
+                self.finalization_delay.set(Some(number - 1));
+                return Ok(number - 1);
+            }
+        }
+        Err(format_err!(
+            "no cellbase output in the first {} blocks, pass the finalization delay explicitly",
+            MAX_FINALIZATION_DELAY + 1
+        ))
+    }
+
+    /// The lowest tip for a scan ending at `anchor`: the cellbase paying the
+    /// anchor and `confirmations` blocks past it. Both the wait before the scan
+    /// and the reorg check after it use this threshold.
+    fn required_tip(&self, anchor: u64) -> Result<u64, Error> {
+        Ok(anchor + self.finalization_delay()? + self.options.confirmations)
+    }
+
+    /// The tip index in epoch `target + 1` required before generating, the
+    /// first block of the epoch follows the anchor.
+    fn ready_index(&self) -> Result<u64, Error> {
+        Ok(self.required_tip(0)?.saturating_sub(1))
+    }
+
+    fn is_ready(&self, tip_header: &HeaderView) -> Result<bool, Error> {
+        let tip_epoch = tip_header.epoch();
+        if tip_epoch.number() <= self.target {
+            return Ok(false);
+        }
+        let next_epoch_start = if tip_epoch.number() == self.target + 1 {
+            tip_header.number() - tip_epoch.index()
+        } else {
+            self.source
+                .get_epoch_by_number((self.target + 1).into())?
+                .ok_or_else(|| format_err!("epoch {} not found", self.target + 1))?
+                .start_number
+                .into()
+        };
+        Ok(tip_header.number() >= self.required_tip(next_epoch_start - 1)?)
+    }
+
+    /// Polls the tip until lina is ready, refreshing the estimate in between.
+    pub fn wait(&self) -> Result<(), Error> {
+        self.wait_with(|seconds| sleep(Duration::from_secs(seconds)))
+    }
+
+    /// `wait` with the pause between two polls, in seconds, left to `pause`.
+    fn wait_with<F: FnMut(u64)>(&self, mut pause: F) -> Result<(), Error> {
+        let spinner = ProgressBar::new_spinner();
+        spinner.set_style(ProgressStyle::default_spinner().template("{spinner} {msg}"));
+        loop {
+            let tip_header = match self.tip_header() {
+                Ok(tip_header) => tip_header,
+                Err(e) => {
+                    spinner.println(format!("get tip header failed: {}, retrying", e));
+                    pause(MIN_POLL_INTERVAL);
+                    continue;
+                }
+            };
+            let ready = match self.is_ready(&tip_header) {
+                Ok(ready) => ready,
+                Err(e) => {
+                    spinner.println(format!("ready check failed: {}, retrying", e));
+                    pause(MIN_POLL_INTERVAL);
+                    continue;
+                }
+            };
+            if ready {
+                spinner.finish_with_message(&format!(
+                    "Lina is ready at block {}, start generating",
+                    tip_header.number()
+                ));
+                return Ok(());
+            }
+
+            let interval = match self.estimate(&tip_header, METRIC_EPOCH) {
+                Ok(estimate) => {
+                    spinner.set_message(&format!(
+                        "tip {} epoch {} ({}/{}), remaining {}, launch at {}",
+                        estimate.tip_number,
+                        estimate.tip_epoch,
+                        estimate.tip_epoch_index,
+                        estimate.tip_epoch_length,
+                        format_duration(estimate.remaining_seconds),
+                        estimate.launch_time_local
+                    ));
+                    // poll more frequently as the launch approaches
+                    (estimate.remaining_seconds / 20)
+                        .max(MIN_POLL_INTERVAL)
+                        .min(MAX_POLL_INTERVAL)
+                }
+                Err(e) => {
+                    spinner.println(format!("estimate failed: {}, retrying", e));
+                    MIN_POLL_INTERVAL
+                }
+            };
+            spinner.tick();
+            pause(interval);
         }
     }
 
     pub fn collect(
         &self,
         map: &mut BTreeMap<Bytes, Capacity>,
-        output: &mut Option<csv::Writer<File>>,
-    ) -> Result<(u64, u32, Byte32, u64), Error> {
-        let tip_header: HeaderView = self.rpc.get_tip_header()?.into();
-        let tip_epoch = tip_header.epoch();
-        if (tip_epoch.number() < (self.target + 1)) || tip_epoch.index() < 11 {
-            self.estimate_launch_time(tip_header)?;
-            exit(1);
+        output: &mut Option<Audit>,
+        rounding: Rounding,
+    ) -> Result<Stage3, Error> {
+        let scan = self.scan(self.target)?;
+        let stage3 = scan.stage3(self.target, rounding)?;
+
+        for (lock, reward_ckb) in &stage3.rewards {
+            let lock: Script = lock.clone().into();
+            let reward = reward_ckb * BYTE_SHANNONS;
+
+            let entry = map
+                .entry(lock.args().raw_data())
+                .or_insert_with(Capacity::zero);
+            *entry = entry.safe_add(reward)?;
+
+            if let Some(audit) = output.as_mut() {
+                let address = Address::new(lock.args().raw_data());
+                let output = Output {
+                    source: "round5.stage3".to_string(),
+                    address: address.testnet_short_format()?,
+                    capacity: *reward_ckb,
+                    lock: None,
+                    code_hash: DEFAULT_CODE_HASH.to_string(),
+                    args: format!("0x{}", faster_hex::hex_string(&address.args[..]).unwrap()),
+                    mainnet_address: address.mainnet_short_format()?,
+                    row: None,
+                    tx_hash: None,
+                    index: None,
+                };
+                audit.push(output);
+            }
         }
 
-        let next_epoch = self
-            .rpc
-            .get_epoch_by_number((self.target + 1).into())?
-            .unwrap_or_else(|| exit(1));
+        Ok(stage3)
+    }
 
-        let next_epoch_start: u64 = next_epoch.start_number.into();
+    /// Scans the chain up to the anchor of `self.target`, snapshotting the miner
+    /// rewards at the end of every epoch from `first_target`.
+    pub fn scan(&self, first_target: u64) -> Result<Scan, Error> {
+        let tip_header: HeaderView = self.source.get_tip_header()?.into();
+        if !self.is_ready(&tip_header)? {
+            self.estimate(&tip_header, METRIC_EPOCH)?.print();
+            exit(1);
+        }
 
-        let endpoint = next_epoch_start - 1;
+        // the last block of each snapshot epoch
+        let mut endpoints = BTreeMap::new();
+        for target in first_target..=self.target {
+            let next_epoch = self
+                .source
+                .get_epoch_by_number((target + 1).into())?
+                .unwrap_or_else(|| exit(1));
+            let next_epoch_start: u64 = next_epoch.start_number.into();
+            endpoints.insert(next_epoch_start - 1, target);
+        }
+        let endpoint = *endpoints.keys().last().expect("non-empty targets");
 
+        let mut scan = Scan::default();
+        let incentives_lock = incentives_lock();
+        let mut reward_epoch = None;
         let mut rewards = HashMap::with_capacity(42);
-        let mut windows = VecDeque::with_capacity(10);
+        let delay = self.finalization_delay()?;
+        let mut windows = VecDeque::with_capacity(delay as usize + 1);
 
-        let progress_bar = ProgressBar::new(endpoint + 11);
+        let progress_bar = ProgressBar::new(endpoint + delay);
         progress_bar.set_style(
             ProgressStyle::default_bar()
                 .template("[{elapsed_precise}] {bar:60.cyan/blue} {pos:>7}/{len:7} {msg}")
                 .progress_chars("##-"),
         );
 
-        for num in 1..=11 {
-            progress_bar.inc(1);
-            if let Some(block) = self.rpc.get_block_by_number(num.into())? {
-                let block: BlockView = block.into();
-                windows.push_back(block);
-            } else {
-                exit(1);
+        let genesis_hash = self
+            .source
+            .get_block_hash(0u64.into())?
+            .unwrap_or_else(|| exit(1));
+        if let Some(expected) = &self.options.genesis_hash {
+            if &genesis_hash != expected {
+                return Err(IntegrityError {
+                    number: 0,
+                    reason: format!("genesis hash {:#x}, expected {:#x}", genesis_hash, expected),
+                }
+                .into());
             }
         }
+        let genesis_hash = genesis_hash.pack();
+        // the hash of the last fetched block
+        let mut parent = genesis_hash.clone();
 
-        for cursor in 12..=(endpoint + 11) {
+        for num in 1..=delay {
             progress_bar.inc(1);
-            if let Some(block) = self.rpc.get_block_by_number(cursor.into())? {
-                let block: BlockView = block.into();
-                windows.push_back(block);
-            } else {
-                exit(1);
-            }
+            let block = self.fetch_cellbase(num, &parent)?;
+            parent = block.header.hash();
+            windows.push_back(block);
+        }
 
-            let hash = self
-                .rpc
-                .get_block_hash(cursor.into())?
-                .unwrap_or_else(|| exit(1));
+        for cursor in (delay + 1)..=(endpoint + delay) {
+            progress_bar.inc(1);
+            let block = self.fetch_cellbase(cursor, &parent)?;
+            let hash: H256 = block.header.hash().unpack();
+            parent = block.header.hash();
+            windows.push_back(block);
 
             let reward = self
-                .rpc
-                .get_cellbase_output_capacity_details(hash)?
+                .source
+                .get_cellbase_output_capacity_details(hash.clone())?
                 .unwrap_or_else(|| exit(1));
-            let target_lock = CellbaseWitness::from_slice(
-                &windows[0].transactions()[0]
-                    .witnesses()
-                    .get(0)
-                    .expect("target witness exist")
-                    .raw_data(),
-            )
-            .expect("cellbase loaded from store should has non-empty witness")
-            .lock();
-
-            let entry = rewards.entry(target_lock).or_insert_with(Capacity::zero);
             let primary: u64 = reward.primary.into();
+            let rewarded = &windows[0].header;
+            if self.options.verify_rewards {
+                self.verify_primary_reward(rewarded, primary, &mut reward_epoch)?;
+            }
+            let target_lock = match miner_lock(windows[0].cellbase.as_ref()) {
+                Ok(lock) if is_sighash_lock(&lock) => lock,
+                found => {
+                    let (lock, reason) = match found {
+                        Ok(lock) => (Some(lock.into()), "invalid miner lock".to_string()),
+                        Err(reason) => {
+                            progress_bar.println(format!(
+                                "block {} {:#x}: {}, rewarded to the incentives lock",
+                                rewarded.number(),
+                                rewarded.hash(),
+                                reason
+                            ));
+                            (None, reason)
+                        }
+                    };
+                    scan.redirected.push(RedirectedBlock {
+                        number: rewarded.number(),
+                        hash: rewarded.hash().unpack(),
+                        lock,
+                        reason,
+                        primary,
+                    });
+                    incentives_lock.clone()
+                }
+            };
+            let entry = rewards.entry(target_lock).or_insert_with(Capacity::zero);
+            if cursor % self.options.cross_check_interval == 0 {
+                self.cross_check(
+                    &format!("block {} primary reward", cursor),
+                    &Some(primary),
+                    |peer| {
+                        Ok(peer
+                            .get_cellbase_output_capacity_details(hash.clone())?
+                            .map(|reward| reward.primary.into()))
+                    },
+                )?;
+            }
 
             *entry = entry.safe_add(primary)?;
-            if cursor != endpoint + 11 {
-                windows.pop_front();
+
+            if let Some(target) = endpoints.get(&rewarded.number()) {
+                let mut snapshot: Vec<(&Script, &Capacity)> = rewards.iter().collect();
+                snapshot.sort_by_key(|(lock, _)| lock.as_slice().to_vec());
+                scan.snapshots.insert(
+                    *target,
+                    Snapshot {
+                        anchor_number: rewarded.number(),
+                        anchor_hash: rewarded.hash().unpack(),
+                        anchor_timestamp: rewarded.timestamp(),
+                        rewards: snapshot
+                            .into_iter()
+                            .map(|(lock, capacity)| (lock.clone().into(), capacity.as_u64()))
+                            .collect(),
+                    },
+                );
+            }
+            windows.pop_front();
+        }
+        progress_bar.finish();
+        // the scanned range is hash linked, its last block and the anchors being
+        // on the main chain of the source and agreed by the peers means the
+        // whole range is.
+        let mut checkpoints = vec![(endpoint + delay, parent)];
+        checkpoints.extend(
+            scan.snapshots
+                .values()
+                .map(|snapshot| (snapshot.anchor_number, snapshot.anchor_hash.pack())),
+        );
+        self.verify_canonical(endpoint, &checkpoints)?;
+        self.cross_check_tip(self.required_tip(endpoint)?)?;
+        self.cross_check_block_hash(0, &genesis_hash)?;
+        for (number, hash) in &checkpoints {
+            self.cross_check_block_hash(*number, hash)?;
+        }
+
+        let first_epoch = first_target.saturating_sub(METRIC_EPOCH - 1);
+        for number in first_epoch..=self.target {
+            let epoch = self
+                .source
+                .get_epoch_by_number(number.into())?
+                .unwrap_or_else(|| exit(1));
+            let compact_target: u32 = epoch.compact_target.into();
+            let start_number: u64 = epoch.start_number.into();
+            let length: u64 = epoch.length.into();
+            self.cross_check(
+                &format!("epoch {}", number),
+                &Some((compact_target, start_number, length)),
+                |peer| {
+                    Ok(peer.get_epoch_by_number(number.into())?.map(|epoch| {
+                        (
+                            epoch.compact_target.into(),
+                            epoch.start_number.into(),
+                            epoch.length.into(),
+                        )
+                    }))
+                },
+            )?;
+            scan.epochs.insert(
+                number,
+                EpochSummary {
+                    compact_target,
+                    length,
+                },
+            );
+        }
+
+        Ok(scan)
+    }
+
+    /// Fetches the header and cellbase of block `number` and checks that it is
+    /// linked to `parent`, that its hash is the hash of the header and that the
+    /// cellbase, including its witness, is committed in the header. The last
+    /// hash of the linked range is compared with `get_block_hash` once the scan
+    /// is done.
+    fn fetch_cellbase(&self, number: u64, parent: &Byte32) -> Result<ScannedBlock, Error> {
+        let view = self
+            .source
+            .get_cellbase_by_number(number.into())?
+            .unwrap_or_else(|| exit(1));
+        let claimed_hash = view.header.hash.pack();
+        let header: HeaderView = view.header.into();
+        let cellbase = view
+            .cellbase
+            .map(|cellbase| Transaction::from(cellbase.inner).into_view());
+        let integrity_error = |reason: String| IntegrityError { number, reason };
+
+        if header.number() != number {
+            return Err(integrity_error(format!("got block {}", header.number())).into());
+        }
+        if &header.parent_hash() != parent {
+            return Err(integrity_error(format!(
+                "parent hash {:#x} does not link to {:#x}",
+                header.parent_hash(),
+                parent
+            ))
+            .into());
+        }
+        let (mut tx_hashes, mut witness_hashes) = match &cellbase {
+            Some(cellbase) => (vec![cellbase.hash()], vec![cellbase.witness_hash()]),
+            None => (vec![], vec![]),
+        };
+        tx_hashes.extend(view.tx_hashes.iter().map(Pack::pack));
+        witness_hashes.extend(view.witness_hashes.iter().map(Pack::pack));
+        let transactions_root =
+            merkle_root(&[merkle_root(&tx_hashes), merkle_root(&witness_hashes)]);
+        if header.transactions_root() != transactions_root {
+            return Err(integrity_error("transactions root mismatch".to_string()).into());
+        }
+        if header.hash() != claimed_hash {
+            return Err(integrity_error(format!(
+                "computed hash {:#x}, claimed {:#x}",
+                header.hash(),
+                claimed_hash
+            ))
+            .into());
+        }
+        Ok(ScannedBlock { header, cellbase })
+    }
+
+    /// Checks the primary reward of `block` reported by the node against the
+    /// issuance schedule. `epoch` caches the number, start and length of the
+    /// epoch of the previous block.
+    fn verify_primary_reward(
+        &self,
+        block: &HeaderView,
+        primary: u64,
+        epoch: &mut Option<(u64, u64, u64)>,
+    ) -> Result<(), Error> {
+        let epoch_number = block.epoch().number();
+        if epoch.map(|(number, _, _)| number) != Some(epoch_number) {
+            let view = self
+                .source
+                .get_epoch_by_number(epoch_number.into())?
+                .ok_or_else(|| format_err!("epoch {} not found", epoch_number))?;
+            *epoch = Some((epoch_number, view.start_number.into(), view.length.into()));
+        }
+        let (_, start_number, length) = epoch.expect("epoch fetched");
+        let integrity_error = |reason: String| IntegrityError {
+            number: block.number(),
+            reason,
+        };
+
+        let index = block
+            .number()
+            .checked_sub(start_number)
+            .filter(|index| *index < length)
+            .ok_or_else(|| {
+                integrity_error(format!(
+                    "not in epoch {} starting at {} with length {}",
+                    epoch_number, start_number, length
+                ))
+            })?;
+        let expected = primary_block_reward(epoch_number, index, length);
+        if primary != expected {
+            return Err(integrity_error(format!(
+                "primary reward {} from the node, {} from the issuance schedule",
+                primary, expected
+            ))
+            .into());
+        }
+        Ok(())
+    }
+
+    /// Re-checks that the `checkpoints`, the last scanned block first, are
+    /// still on the main chain and that the tip is still at the required tip
+    /// of `anchor`.
+    fn verify_canonical(&self, anchor: u64, checkpoints: &[(u64, Byte32)]) -> Result<(), Error> {
+        let required = self.required_tip(anchor)?;
+        let tip_header = self.tip_header()?;
+        if tip_header.number() < required {
+            return Err(ReorgError {
+                number: tip_header.number(),
+                reason: format!(
+                    "tip is {} blocks past the anchor, {} required",
+                    tip_header.number().saturating_sub(anchor),
+                    required - anchor
+                ),
+            }
+            .into());
+        }
+
+        for (number, expected) in checkpoints {
+            let number = *number;
+            let canonical =
+                self.source
+                    .get_block_hash(number.into())?
+                    .ok_or_else(|| ReorgError {
+                        number,
+                        reason: "block is no longer on the main chain".to_string(),
+                    })?;
+            if &canonical.pack() != expected {
+                return Err(ReorgError {
+                    number,
+                    reason: format!("expected hash {:#x}, got {:#x}", expected, canonical),
+                }
+                .into());
             }
         }
-        let chosen_one = windows.pop_front().unwrap_or_else(|| exit(1));
-        rewards.retain(|_, &mut r| r > THRESHOLD);
+        Ok(())
+    }
+
+    pub fn tip_header(&self) -> Result<HeaderView, Error> {
+        Ok(self.source.get_tip_header()?.into())
+    }
+
+    pub fn estimate(&self, tip_header: &HeaderView, window: u64) -> Result<Estimate, Error> {
+        let tip_epoch = tip_header.epoch();
+        let length = tip_epoch.length() as f64;
+        let ready_index = self.ready_index()?;
+        let remaining_epochs = if self.is_ready(tip_header)? {
+            0f64
+        } else if tip_epoch.number() == self.target + 1 {
+            (ready_index - tip_epoch.index()) as f64 / length
+        } else {
+            (self.target - tip_epoch.number()) as f64
+                + (tip_epoch.length() - tip_epoch.index() + ready_index) as f64 / length
+        };
+
+        let durations = if window == 0 || tip_epoch.number() < window {
+            vec![]
+        } else {
+            // start timestamps of the last `window` full epochs and the tip epoch
+            let mut timestamps = Vec::with_capacity(window as usize + 1);
+            for number in (tip_epoch.number() - window)..tip_epoch.number() {
+                let epoch = self
+                    .source
+                    .get_epoch_by_number(number.into())?
+                    .ok_or_else(|| format_err!("epoch {} not found", number))?;
+                timestamps.push(self.header_timestamp(epoch.start_number.into())?);
+            }
+            timestamps.push(self.header_timestamp(tip_header.number() - tip_epoch.index())?);
+            timestamps
+                .windows(2)
+                .map(|pair| pair[1].saturating_sub(pair[0]) / 1000)
+                .collect()
+        };
+
+        Ok(Estimate::new(
+            self.target,
+            ready_index,
+            tip_header,
+            remaining_epochs,
+            &durations,
+            DEFAULT_EPOCH_DURATION,
+        ))
+    }
+
+    fn header_timestamp(&self, number: u64) -> Result<u64, Error> {
+        let header = self
+            .source
+            .get_header_by_number(number.into())?
+            .ok_or_else(|| format_err!("header {} not found", number))?;
+        Ok(header.inner.timestamp.into())
+    }
+}
+
+#[derive(Debug, Clone, Fail)]
+pub struct ReorgError {
+    pub number: u64,
+    pub reason: String,
+}
+
+impl fmt::Display for ReorgError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "reorg detected at block {}: {}", self.number, self.reason)
+    }
+}
+
+/// The part of a block the scan keeps until the block is rewarded.
+struct ScannedBlock {
+    header: HeaderView,
+    cellbase: Option<TransactionView>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct EpochSummary {
+    pub compact_target: u32,
+    pub length: u64,
+}
+
+/// Accumulated primary rewards of each miner lock up to the anchor of a target.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Snapshot {
+    /// The last block in the target epoch.
+    pub anchor_number: u64,
+    pub anchor_hash: H256,
+    pub anchor_timestamp: u64,
+    pub rewards: Vec<(JsonScript, u64)>,
+}
+
+/// A block mined by a lock that cannot be rewarded in lina, or whose miner lock
+/// cannot be read, its reward goes to the foundation testnet incentives lock.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RedirectedBlock {
+    pub number: u64,
+    pub hash: H256,
+    /// `None` when the cellbase witness is missing or malformed.
+    pub lock: Option<JsonScript>,
+    pub reason: String,
+    pub primary: u64,
+}
+
+/// Everything the round 5 stage 3 computation needs from the chain, so several
+/// targets can be evaluated from a single scan, or from a saved one.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Scan {
+    pub snapshots: BTreeMap<u64, Snapshot>,
+    pub epochs: BTreeMap<u64, EpochSummary>,
+    #[serde(default)]
+    pub redirected: Vec<RedirectedBlock>,
+}
+
+/// How the stage 3 rewards are rounded to whole CKBytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Rounding {
+    /// Round every reward down, the loss goes to the testnet incentives remainder.
+    Floor,
+    /// Round down, then give the lost CKBytes to the largest remainders.
+    LargestRemainder,
+}
+
+pub struct Stage3 {
+    pub target: u64,
+    /// Reward in CKBytes of each miner lock above the threshold.
+    pub rewards: Vec<(JsonScript, u64)>,
+    /// Total primary rewards of the miners above the threshold.
+    pub total: Capacity,
+    /// Part of `TOTAL_REWARD` not given to any miner because of rounding down
+    /// to whole CKBytes, it goes to the testnet incentives remainder.
+    pub rounding_loss: Capacity,
+    pub compact_target: u32,
+    pub timestamp: u64,
+    pub anchor_number: u64,
+    pub anchor_hash: H256,
+    pub epoch_length: u64,
+    /// Blocks up to the anchor whose reward is redirected to the incentives lock.
+    pub redirected: Vec<RedirectedBlock>,
+}
+
+impl Scan {
+    pub fn stage3(&self, target: u64, rounding: Rounding) -> Result<Stage3, Error> {
+        let snapshot = self
+            .snapshots
+            .get(&target)
+            .ok_or_else(|| format_err!("target {} is not scanned", target))?;
+
+        let rewards: Vec<_> = snapshot
+            .rewards
+            .iter()
+            .filter(|(_, capacity)| Capacity::shannons(*capacity) > THRESHOLD)
+            .collect();
 
         let total = rewards
             .iter()
-            .map(|(_, capacity)| *capacity)
+            .map(|(_, capacity)| Capacity::shannons(*capacity))
             .try_fold(Capacity::zero(), Capacity::safe_add)?;
 
-        if let Some(wtr) = output.as_mut() {
-            wtr.write_record(&["#round5.stage3", "", "", "", "", ""])
-                .unwrap();
+        if total == Capacity::zero() {
+            return Err(format_err!(
+                "no miner is above the threshold at target {}",
+                target
+            ));
         }
 
+        // reward_ckb = floor(TOTAL_REWARD_CKB * capacity / total), computed with the
+        // remainder so the rounding loss can be accounted for exactly.
+        let total_reward_ckb = TOTAL_REWARD.as_u64() / BYTE_SHANNONS;
+        let mut shares = Vec::with_capacity(rewards.len());
         for (lock, capacity) in rewards {
-            let ratio =
-                RationalU256::new(U256::from(capacity.as_u64()), U256::from(total.as_u64()));
-            let total = RationalU256::new(U256::from(TOTAL_REWARD.as_u64()), U256::one());
-            let reward_ckb = get_low64(&(total * ratio).into_u256()) / BYTE_SHANNONS;
-            let reward = reward_ckb * BYTE_SHANNONS;
-
-            let entry = map
-                .entry(lock.args().raw_data())
-                .or_insert_with(Capacity::zero);
-            *entry = entry.safe_add(reward)?;
+            let numerator = U256::from(total_reward_ckb) * U256::from(*capacity);
+            let quotient = numerator.clone() / U256::from(total.as_u64());
+            let remainder = numerator - quotient.clone() * U256::from(total.as_u64());
+            shares.push((lock.clone(), get_low64(&quotient)?, get_low64(&remainder)?));
+        }
 
-            if let Some(wtr) = output.as_mut() {
-                let address = Address::new(lock.args().raw_data());
-                let output = Output {
-                    address: address.testnet_short_format()?,
-                    capacity: reward_ckb,
-                    lock: None,
-                    code_hash: DEFAULT_CODE_HASH.to_string(),
-                    args: format!("0x{}", faster_hex::hex_string(&address.args[..]).unwrap()),
-                    mainnet_address: address.mainnet_short_format()?,
-                };
-                wtr.serialize(output)?;
+        let floor_ckb: u64 = shares.iter().map(|(_, reward_ckb, _)| reward_ckb).sum();
+        let mut rounding_loss_ckb = total_reward_ckb - floor_ckb;
+        if rounding == Rounding::LargestRemainder {
+            // hand out the lost CKBytes one by one, ties go to the earlier lock
+            let mut order: Vec<_> = (0..shares.len()).collect();
+            order.sort_by(|a, b| shares[*b].2.cmp(&shares[*a].2));
+            for i in order.into_iter().take(rounding_loss_ckb as usize) {
+                shares[i].1 += 1;
             }
+            rounding_loss_ckb = 0;
         }
+        let rewards = shares
+            .into_iter()
+            .map(|(lock, reward_ckb, _)| (lock, reward_ckb))
+            .collect();
 
-        let epochs: Vec<_> = (0..METRIC_EPOCH)
+        let epochs = (0..METRIC_EPOCH)
             .map(|i| {
-                self.rpc
-                    .get_epoch_by_number((self.target - i).into())
-                    .unwrap_or_else(|_| exit(1))
-                    .unwrap_or_else(|| exit(1))
+                self.epochs
+                    .get(&(target - i))
+                    .ok_or_else(|| format_err!("epoch {} is not scanned", target - i))
             })
-            .collect();
+            .collect::<Result<Vec<_>, _>>()?;
 
         let avg_diff: U256 = epochs
             .iter()
-            .map(|epoch| compact_to_difficulty(epoch.compact_target.into()))
+            .map(|epoch| compact_to_difficulty(epoch.compact_target))
             .fold(U256::zero(), U256::add)
             / U256::from(METRIC_EPOCH);
 
         let diff = (avg_diff * U256::from(3u64) / U256::from(2u64)) * U256::from(total.as_u64())
             / U256::from(TOTAL_REWARD.as_u64());
 
-        let compact_target = difficulty_to_compact(diff);
+        Ok(Stage3 {
+            target,
+            rewards,
+            total,
+            rounding_loss: Capacity::shannons(rounding_loss_ckb * BYTE_SHANNONS),
+            compact_target: difficulty_to_compact(diff),
+            timestamp: snapshot.anchor_timestamp,
+            anchor_number: snapshot.anchor_number,
+            anchor_hash: snapshot.anchor_hash.clone(),
+            epoch_length: epochs[0].length,
+            redirected: self
+                .redirected
+                .iter()
+                .filter(|block| block.number <= snapshot.anchor_number)
+                .cloned()
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Fail)]
+pub struct DisagreementError {
+    pub url: String,
+    pub reason: String,
+}
 
-        progress_bar.finish();
-        Ok((
-            chosen_one.timestamp(),
-            compact_target,
-            chosen_one.hash(),
-            epochs[0].length.into(),
-        ))
+impl fmt::Display for DisagreementError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "endpoint {} disagrees on {}", self.url, self.reason)
     }
+}
 
-    pub fn estimate_launch_time(&self, tip_header: HeaderView) -> Result<(), Error> {
-        let now = Local::now();
-        let tip_epoch = tip_header.epoch();
+#[derive(Debug, Clone, Fail)]
+pub struct IntegrityError {
+    pub number: u64,
+    pub reason: String,
+}
 
-        let avg_epoch_duration = if tip_epoch.number() < METRIC_EPOCH {
-            4 * 3600
-        } else {
-            // get average elapsed time in the last four full epochs
-            let first_epoch = self
-                .rpc
-                .get_epoch_by_number((tip_epoch.number() - METRIC_EPOCH).into())
-                .unwrap_or_else(|_| exit(1))
-                .unwrap_or_else(|| exit(1));
-            let first_block = self
-                .rpc
-                .get_header_by_number(first_epoch.start_number)?
-                .unwrap_or_else(|| exit(1));
-            let last_block = self
-                .rpc
-                .get_header_by_number(
-                    (Into::<u64>::into(tip_header.number()) - tip_epoch.index()).into(),
-                )?
-                .unwrap_or_else(|| exit(1));
-            let t1: u64 = first_block.inner.timestamp.into();
-            let t2: u64 = last_block.inner.timestamp.into();
-            (t2 - t1) / METRIC_EPOCH / 1000
+impl fmt::Display for IntegrityError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid block {}: {}", self.number, self.reason)
+    }
+}
+
+/// Primary issuance of the `index`th block in an epoch, the epoch reward is
+/// split evenly and the first `epoch_reward % length` blocks get one more
+/// shannon, like `EpochExt::block_reward` in ckb.
+pub fn primary_block_reward(epoch_number: u64, index: u64, length: u64) -> u64 {
+    let halvings = epoch_number / PRIMARY_EPOCH_REWARD_HALVING_INTERVAL;
+    let epoch_reward = INITIAL_PRIMARY_EPOCH_REWARD
+        .as_u64()
+        .checked_shr(halvings as u32)
+        .unwrap_or(0);
+    let base = epoch_reward / length;
+    if index < epoch_reward % length {
+        base + 1
+    } else {
+        base
+    }
+}
+
+/// Whether the cellbase of `block` has outputs, which it has once the chain is
+/// past the finalization delay.
+pub fn pays_reward(block: &BlockView) -> bool {
+    block
+        .transactions()
+        .first()
+        .map_or(false, |cellbase| !cellbase.outputs().is_empty())
+}
+
+/// Reads the miner lock from the witness of `cellbase`.
+fn miner_lock(cellbase: Option<&TransactionView>) -> Result<Script, String> {
+    let cellbase = cellbase.ok_or_else(|| "no cellbase".to_string())?;
+    let witness = cellbase
+        .witnesses()
+        .get(0)
+        .ok_or_else(|| "cellbase has no witness".to_string())?;
+    CellbaseWitness::from_slice(&witness.raw_data())
+        .map(|witness| witness.lock())
+        .map_err(|e| format!("malformed cellbase witness: {}", e))
+}
+
+/// Only secp256k1_blake160_sighash_all locks with a public key hash are valid
+/// miner locks, like the addresses in the input CSV files.
+fn is_sighash_lock(lock: &Script) -> bool {
+    format!("{:#x}", lock.code_hash()) == DEFAULT_CODE_HASH
+        && lock.hash_type() == ScriptHashType::Type.into()
+        && lock.args().raw_data().len() == 20
+}
+
+fn incentives_lock() -> Script {
+    Script::new_builder()
+        .code_hash(H256::from_str(&DEFAULT_CODE_HASH[2..]).unwrap().pack())
+        .hash_type(ScriptHashType::Type.into())
+        .args(Address::from_str(INCENTIVES_ADDRESS).unwrap().args.pack())
+        .build()
+}
+
+fn get_low64(u256: &U256) -> Result<u64, Error> {
+    if u256.0[1..].iter().any(|limb| *limb != 0) {
+        return Err(format_err!("{} does not fit in 64 bits", u256));
+    }
+    Ok(u256.0[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::{MockChain, MockChainConfig, MockTransport, ReorgSource};
+    use crate::source::CellbaseView;
+    use ckb_jsonrpc_types as rpc_types;
+    use std::rc::Rc;
+    use std::sync::{Arc, RwLock};
+
+    // target 4 ends at block 99, rewarded by block 110 with the default delay
+    const LAST_SCANNED: u64 = 110;
+
+    fn other_miners() -> MockChain {
+        MockChain::new(MockChainConfig {
+            miners: vec![Bytes::from(vec![7u8; 20])],
+            ..Default::default()
+        })
+    }
+
+    /// An explorer for target 4 on `chain`.
+    fn chain_explorer(chain: MockChain, options: Options) -> Explorer {
+        Explorer::with_sources(Box::new(MockTransport::client(chain)), vec![], 4, options)
+    }
+
+    fn mock_explorer(config: MockChainConfig, options: Options) -> Explorer {
+        chain_explorer(MockChain::new(config), options)
+    }
+
+    #[test]
+    fn test_pin_genesis_hash() {
+        let genesis = MockChain::new(MockChainConfig::default()).blocks[0]
+            .hash()
+            .unpack();
+        let pinned = |genesis_hash| Options {
+            genesis_hash: Some(genesis_hash),
+            ..Default::default()
         };
+        assert!(mock_explorer(MockChainConfig::default(), pinned(genesis))
+            .scan(4)
+            .is_ok());
 
-        let remaining_seconds = (self.target.saturating_sub(tip_epoch.number()))
-            * avg_epoch_duration
-            + avg_epoch_duration * (tip_epoch.length() - tip_epoch.index() + 11)
-                / tip_epoch.length();
-        let remaining_duration = Duration::seconds(remaining_seconds as i64);
+        let err = mock_explorer(MockChainConfig::default(), pinned(H256::zero()))
+            .scan(4)
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<IntegrityError>()
+            .expect("integrity error");
+        assert_eq!(err.number, 0);
+    }
 
-        println!(
-            "Lina is not ready yet. Please wait for the 11st block in epoch {}.",
-            self.target + 1
+    #[test]
+    fn test_reorg_after_scan() {
+        let source = ReorgSource::new(
+            MockChain::new(MockChainConfig::default()),
+            other_miners(),
+            LAST_SCANNED,
         );
-        print!("Estimated remaining time: ");
-        if remaining_seconds > 86400 {
-            print!("{} days ", remaining_seconds / 86400)
-        }
-        println!(
-            "{:02}h{:02}m{:02}s",
-            remaining_seconds / 3600 % 24,
-            remaining_seconds / 60 % 60,
-            remaining_seconds % 60
+        let explorer = Explorer::with_sources(Box::new(source), vec![], 4, Options::default());
+        let err = explorer.scan(4).unwrap_err();
+        let err = err.downcast_ref::<ReorgError>().expect("reorg error");
+        assert_eq!(err.number, LAST_SCANNED);
+    }
+
+    #[test]
+    fn test_tip_rolled_back_after_scan() {
+        let mut shorter = MockChain::new(MockChainConfig::default());
+        shorter.blocks.truncate(LAST_SCANNED as usize + 3);
+        let source = ReorgSource::new(
+            MockChain::new(MockChainConfig::default()),
+            shorter,
+            LAST_SCANNED,
         );
-        println!("Estimated launch time: {}", now + remaining_duration);
+        let options = Options {
+            confirmations: 5,
+            ..Default::default()
+        };
+        let explorer = Explorer::with_sources(Box::new(source), vec![], 4, options);
+        let err = explorer.scan(4).unwrap_err();
+        let err = err.downcast_ref::<ReorgError>().expect("reorg error");
+        assert_eq!(err.number, LAST_SCANNED + 2);
+        assert_eq!(err.reason, "tip is 13 blocks past the anchor, 16 required");
+    }
 
-        Ok(())
+    fn uneven_scan() -> Scan {
+        let mut scan = Scan::default();
+        let rewards = [3_000u64, 3_000, 3_001]
+            .iter()
+            .enumerate()
+            .map(|(i, capacity)| {
+                let lock = Script::new_builder()
+                    .args(Bytes::from(vec![i as u8; 20]).pack())
+                    .build();
+                (lock.into(), capacity * BYTE_SHANNONS)
+            })
+            .collect();
+        scan.snapshots.insert(
+            4,
+            Snapshot {
+                anchor_number: 99,
+                anchor_hash: Default::default(),
+                anchor_timestamp: 0,
+                rewards,
+            },
+        );
+        for number in 1..=4 {
+            scan.epochs.insert(
+                number,
+                EpochSummary {
+                    compact_target: 0x2001_0000,
+                    length: 20,
+                },
+            );
+        }
+        scan
+    }
+
+    fn total_ckb(stage3: &Stage3) -> u64 {
+        stage3
+            .rewards
+            .iter()
+            .map(|(_, reward_ckb)| reward_ckb)
+            .sum()
     }
-}
 
-fn get_low64(u256: &U256) -> u64 {
-    u256.0[0]
+    #[test]
+    fn test_stage3_floor_rounding_loss() {
+        let stage3 = uneven_scan().stage3(4, Rounding::Floor).unwrap();
+        let loss = stage3.rounding_loss.as_u64() / BYTE_SHANNONS;
+        assert!(loss > 0);
+        assert_eq!(total_ckb(&stage3) + loss, 18_000_000);
+    }
+
+    #[test]
+    fn test_stage3_largest_remainder() {
+        let scan = uneven_scan();
+        let floor = scan.stage3(4, Rounding::Floor).unwrap();
+        let apportioned = scan.stage3(4, Rounding::LargestRemainder).unwrap();
+        assert_eq!(apportioned.rounding_loss, Capacity::zero());
+        assert_eq!(total_ckb(&apportioned), 18_000_000);
+        for ((_, floor), (_, apportioned)) in floor.rewards.iter().zip(&apportioned.rewards) {
+            assert!(apportioned - floor <= 1);
+        }
+        assert_eq!(floor.compact_target, apportioned.compact_target);
+    }
+
+    #[test]
+    fn test_reject_peer_behind_required_tip() {
+        let mut behind = MockChain::new(MockChainConfig::default());
+        behind.blocks.truncate(LAST_SCANNED as usize);
+        let explorer = Explorer::with_sources(
+            Box::new(MockTransport::client(MockChain::new(
+                MockChainConfig::default(),
+            ))),
+            vec![Box::new(MockTransport::client(behind))],
+            4,
+            Options::default(),
+        );
+        let err = explorer.scan(4).unwrap_err();
+        let err = err
+            .downcast_ref::<DisagreementError>()
+            .expect("disagreement error");
+        assert_eq!(err.reason, "tip 109 is below the required tip 110");
+    }
+
+    #[test]
+    fn test_redirect_invalid_miner_lock() {
+        let explorer = mock_explorer(
+            MockChainConfig {
+                miners: vec![
+                    Bytes::from(vec![1u8; 20]),
+                    Bytes::from(vec![2u8; 20]),
+                    Bytes::from(vec![4u8; 21]),
+                ],
+                ..Default::default()
+            },
+            Options::default(),
+        );
+        let mut records = BTreeMap::new();
+        let stage3 = explorer
+            .collect(&mut records, &mut None, Rounding::Floor)
+            .unwrap();
+
+        // blocks 1 to 99 are rewarded, every third one from block 2 is invalid
+        assert_eq!(stage3.redirected.len(), 33);
+        assert!(stage3.redirected.iter().all(|block| block.number % 3 == 2
+            && block.lock.as_ref().unwrap().args.as_bytes().len() == 21));
+        let incentives_address = Address::from_str(INCENTIVES_ADDRESS).unwrap();
+        assert_eq!(
+            records.keys().cloned().collect::<Vec<_>>(),
+            vec![
+                Bytes::from(vec![1u8; 20]),
+                Bytes::from(vec![2u8; 20]),
+                incentives_address.args.clone()
+            ]
+        );
+    }
+
+    #[test]
+    fn test_redirect_malformed_witness() {
+        let explorer = mock_explorer(
+            MockChainConfig {
+                malformed_witnesses: vec![10, 20],
+                ..Default::default()
+            },
+            Options::default(),
+        );
+        let stage3 = explorer
+            .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+            .unwrap();
+
+        let malformed: Vec<_> = stage3
+            .redirected
+            .iter()
+            .map(|block| (block.number, block.lock.is_none()))
+            .collect();
+        assert_eq!(malformed, vec![(10, true), (20, true)]);
+        assert!(stage3.redirected[0].reason.starts_with("malformed"));
+    }
+
+    fn verify_rewards() -> Options {
+        Options {
+            verify_rewards: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_verify_scheduled_rewards() {
+        // 16 divides the initial primary epoch reward
+        let explorer = mock_explorer(
+            MockChainConfig {
+                epoch_length: 16,
+                primary_reward: 11_986_301369863,
+                ..Default::default()
+            },
+            verify_rewards(),
+        );
+        explorer
+            .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+            .unwrap();
+    }
+
+    #[test]
+    fn test_reject_unscheduled_rewards() {
+        let explorer = mock_explorer(MockChainConfig::default(), verify_rewards());
+        let err = explorer
+            .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<IntegrityError>()
+            .expect("integrity error");
+        assert_eq!(err.number, 1);
+    }
+
+    #[test]
+    fn test_infer_finalization_delay() {
+        let config = MockChainConfig {
+            finalization_delay: 5,
+            malformed_witnesses: vec![99],
+            ..Default::default()
+        };
+        let explorer = mock_explorer(config.clone(), Options::default());
+        assert_eq!(explorer.finalization_delay().unwrap(), 5);
+
+        // the last rewarded block is still the anchor
+        let stage3 = explorer
+            .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+            .unwrap();
+        assert_eq!(stage3.anchor_number, 99);
+        assert_eq!(
+            stage3
+                .redirected
+                .iter()
+                .map(|block| block.number)
+                .collect::<Vec<_>>(),
+            vec![99]
+        );
+
+        let explicit = mock_explorer(
+            config,
+            Options {
+                finalization_delay: Some(5),
+                ..Default::default()
+            },
+        )
+        .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+        .unwrap();
+        assert_eq!(explicit.anchor_hash, stage3.anchor_hash);
+        assert_eq!(explicit.compact_target, stage3.compact_target);
+    }
+
+    #[test]
+    fn test_reject_broken_hash_chain() {
+        let mut chain = MockChain::new(MockChainConfig::default());
+        chain.blocks[50] = chain.config.build_block(50, Byte32::zero());
+
+        let err = chain_explorer(chain, Options::default())
+            .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<IntegrityError>()
+            .expect("integrity error");
+        assert_eq!(err.number, 50);
+    }
+
+    /// Counts the `get_block_hash` calls, adds a witness to the cellbase of
+    /// block `tampered` and fails the first `tip_errors` tip requests.
+    struct Probe {
+        inner: RpcClient,
+        block_hash_calls: Rc<Cell<u64>>,
+        tampered: Option<u64>,
+        tip_errors: Cell<u64>,
+    }
+
+    impl ChainSource for Probe {
+        fn name(&self) -> String {
+            self.inner.url.clone()
+        }
+
+        fn get_block_by_number(
+            &self,
+            number: rpc_types::BlockNumber,
+        ) -> Result<Option<rpc_types::BlockView>, Error> {
+            self.inner.get_block_by_number(number)
+        }
+
+        fn get_header_by_number(
+            &self,
+            number: rpc_types::BlockNumber,
+        ) -> Result<Option<rpc_types::HeaderView>, Error> {
+            self.inner.get_header_by_number(number)
+        }
+
+        fn get_block_hash(&self, number: rpc_types::BlockNumber) -> Result<Option<H256>, Error> {
+            self.block_hash_calls.set(self.block_hash_calls.get() + 1);
+            self.inner.get_block_hash(number)
+        }
+
+        fn get_cellbase_by_number(
+            &self,
+            number: rpc_types::BlockNumber,
+        ) -> Result<Option<CellbaseView>, Error> {
+            let tampered =
+                self.tampered.map(rpc_types::BlockNumber::from).as_ref() == Some(&number);
+            let mut view = ChainSource::get_cellbase_by_number(&self.inner, number)?;
+            if let Some(cellbase) = view.as_mut().and_then(|view| view.cellbase.as_mut()) {
+                if tampered {
+                    cellbase
+                        .inner
+                        .witnesses
+                        .push(rpc_types::JsonBytes::from_vec(vec![0]));
+                }
+            }
+            Ok(view)
+        }
+
+        fn get_cellbase_output_capacity_details(
+            &self,
+            hash: H256,
+        ) -> Result<Option<rpc_types::BlockReward>, Error> {
+            self.inner.get_cellbase_output_capacity_details(hash)
+        }
+
+        fn get_tip_header(&self) -> Result<rpc_types::HeaderView, Error> {
+            if self.tip_errors.get() > 0 {
+                self.tip_errors.set(self.tip_errors.get() - 1);
+                return Err(format_err!("connection refused"));
+            }
+            self.inner.get_tip_header()
+        }
+
+        fn get_epoch_by_number(
+            &self,
+            number: rpc_types::EpochNumber,
+        ) -> Result<Option<rpc_types::EpochView>, Error> {
+            self.inner.get_epoch_by_number(number)
+        }
+    }
+
+    fn probe(block_hash_calls: &Rc<Cell<u64>>, tampered: Option<u64>) -> Explorer {
+        let probe = Probe {
+            inner: MockTransport::client(MockChain::new(MockChainConfig::default())),
+            block_hash_calls: Rc::clone(block_hash_calls),
+            tampered,
+            tip_errors: Cell::new(0),
+        };
+        Explorer::with_sources(Box::new(probe), vec![], 4, Options::default())
+    }
+
+    #[test]
+    fn test_scan_reads_cellbases() {
+        let expected = mock_explorer(MockChainConfig::default(), Options::default())
+            .scan(4)
+            .unwrap();
+
+        let block_hash_calls = Rc::new(Cell::new(0));
+        let scan = probe(&block_hash_calls, None).scan(4).unwrap();
+        assert_eq!(
+            serde_json::to_value(&scan).unwrap(),
+            serde_json::to_value(&expected).unwrap()
+        );
+        // the genesis, the last scanned block and the anchor, however long
+        // the scanned range is
+        assert_eq!(block_hash_calls.get(), 3);
+
+        let err = probe(&block_hash_calls, Some(30))
+            .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<IntegrityError>()
+            .expect("integrity error");
+        assert_eq!(err.number, 30);
+        assert_eq!(err.reason, "transactions root mismatch");
+    }
+
+    #[test]
+    fn test_reject_disagreeing_endpoint() {
+        let peer = MockTransport {
+            chain: Arc::new(RwLock::new(MockChain::new(MockChainConfig {
+                primary_reward: 1_000_00000000,
+                ..Default::default()
+            }))),
+        };
+        let explorer = Explorer::with_sources(
+            Box::new(MockTransport::client(MockChain::new(
+                MockChainConfig::default(),
+            ))),
+            vec![Box::new(RpcClient::with_transport(
+                "mock://peer",
+                Box::new(peer),
+            ))],
+            4,
+            Options::default(),
+        );
+        let err = explorer
+            .collect(&mut BTreeMap::new(), &mut None, Rounding::Floor)
+            .unwrap_err();
+        let err = err
+            .downcast_ref::<DisagreementError>()
+            .expect("disagreement error");
+        assert_eq!(err.url, "mock://peer");
+    }
+
+    #[test]
+    fn test_wait_polls_until_ready() {
+        let full = MockChain::new(MockChainConfig::default());
+        let mut growing = MockChain::new(MockChainConfig::default());
+        growing.blocks.truncate(LAST_SCANNED as usize - 5);
+        let chain = Arc::new(RwLock::new(growing));
+        let transport = MockTransport {
+            chain: Arc::clone(&chain),
+        };
+        let probe = Probe {
+            inner: RpcClient::with_transport("mock://chain", Box::new(transport)),
+            block_hash_calls: Rc::new(Cell::new(0)),
+            tampered: None,
+            tip_errors: Cell::new(2),
+        };
+        let explorer = Explorer::with_sources(Box::new(probe), vec![], 4, Options::default());
+
+        // one block is mined during every pause
+        let mut pauses = vec![];
+        explorer
+            .wait_with(|seconds| {
+                pauses.push(seconds);
+                let mut chain = chain.write().unwrap();
+                let next = full.blocks[chain.blocks.len()].clone();
+                chain.blocks.push(next);
+            })
+            .unwrap();
+
+        // two failed tip requests, then tips 106 to 109 before 110 is ready
+        assert_eq!(pauses.len(), 6);
+        assert!(pauses
+            .iter()
+            .all(|seconds| (MIN_POLL_INTERVAL..=MAX_POLL_INTERVAL).contains(seconds)));
+        assert_eq!(chain.read().unwrap().tip().number(), LAST_SCANNED);
+    }
 }
@@ -0,0 +1,34 @@
+use ckb_chain_spec::{consensus::Consensus, ChainSpec};
+use ckb_types::{
+    core::Capacity,
+    packed::{Byte32, Script},
+    prelude::*,
+};
+use failure::{format_err, Error};
+
+/// Returns the genesis cellbase hash and the output index of every issued cell
+/// in `spec`, in the order of `spec.genesis.issued_cells`.
+pub fn issued_cell_out_points(
+    spec: &ChainSpec,
+    consensus: &Consensus,
+) -> Result<(Byte32, Vec<u32>), Error> {
+    let cellbase = &consensus.genesis_block().transactions()[0];
+    let outputs: Vec<_> = cellbase.outputs().into_iter().collect();
+
+    let mut indices = Vec::with_capacity(spec.genesis.issued_cells.len());
+    let mut cursor = 0;
+    for (i, cell) in spec.genesis.issued_cells.iter().enumerate() {
+        let lock: Script = cell.lock.clone().into();
+        let position = outputs[cursor..]
+            .iter()
+            .position(|output| {
+                output.lock() == lock
+                    && Unpack::<Capacity>::unpack(&output.capacity()) == cell.capacity
+            })
+            .ok_or_else(|| format_err!("issued cell {} not found in genesis cellbase", i))?;
+        cursor += position;
+        indices.push(cursor as u32);
+        cursor += 1;
+    }
+    Ok((cellbase.hash(), indices))
+}
@@ -0,0 +1,221 @@
+use crate::address::Address;
+use crate::input::{RawRecord, TestnetIncentives};
+use crate::BYTE_SHANNONS;
+use ckb_types::{bytes::Bytes, core::Capacity};
+use failure::Error;
+use serde_json::json;
+use std::collections::BTreeMap;
+use std::convert::TryInto;
+use std::fs;
+
+/// Testnet incentives of each pubkey hash, split by round and stage.
+pub struct Breakdown {
+    pool: Capacity,
+    sources: Vec<String>,
+    amounts: BTreeMap<Bytes, BTreeMap<String, Capacity>>,
+}
+
+impl Breakdown {
+    pub fn new(pool: Capacity) -> Breakdown {
+        Breakdown {
+            pool,
+            sources: vec![],
+            amounts: BTreeMap::new(),
+        }
+    }
+
+    pub fn add(&mut self, source: &str, args: Bytes, capacity: Capacity) -> Result<(), Error> {
+        if !self.sources.iter().any(|s| s == source) {
+            self.sources.push(source.to_string());
+        }
+        let entry = self
+            .amounts
+            .entry(args)
+            .or_insert_with(BTreeMap::new)
+            .entry(source.to_string())
+            .or_insert_with(Capacity::zero);
+        *entry = entry.safe_add(capacity)?;
+        Ok(())
+    }
+
+    /// Adds the valid records of an input CSV, skipping invalid addresses the
+    /// same way `parse_mining_competition_record` does.
+    pub fn add_records<R: IntoIterator<Item = RawRecord>>(
+        &mut self,
+        source: &str,
+        records: R,
+    ) -> Result<(), Error> {
+        for record in records {
+            if let Ok(TestnetIncentives { args, capacity }) = record.try_into() {
+                self.add(source, args, capacity)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds the difference between two aggregated maps, used for the rewards
+    /// computed from the chain.
+    pub fn add_diff(
+        &mut self,
+        source: &str,
+        before: &BTreeMap<Bytes, Capacity>,
+        after: &BTreeMap<Bytes, Capacity>,
+    ) -> Result<(), Error> {
+        if !self.sources.iter().any(|s| s == source) {
+            self.sources.push(source.to_string());
+        }
+        for (args, capacity) in after {
+            let previous = before.get(args).cloned().unwrap_or_else(Capacity::zero);
+            let diff = capacity.safe_sub(previous)?;
+            if diff != Capacity::zero() {
+                self.add(source, args.clone(), diff)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn total(&self, amounts: &BTreeMap<String, Capacity>) -> Result<Capacity, Error> {
+        amounts
+            .values()
+            .try_fold(Capacity::zero(), |sum, capacity| sum.safe_add(*capacity))
+            .map_err(Into::into)
+    }
+
+    fn share(&self, total: Capacity) -> f64 {
+        total.as_u64() as f64 / self.pool.as_u64() as f64
+    }
+
+    /// Writes the breakdown as CSV, or as JSON if `path` ends with `.json`.
+    pub fn write(&self, path: &str) -> Result<(), Error> {
+        if path.ends_with(".json") {
+            self.write_json(path)
+        } else {
+            self.write_csv(path)
+        }
+    }
+
+    fn write_csv(&self, path: &str) -> Result<(), Error> {
+        let mut wtr = csv::Writer::from_path(path)?;
+        let mut header = vec!["mainnet_address".to_string(), "args".to_string()];
+        header.extend(self.sources.iter().cloned());
+        header.push("total".to_string());
+        header.push("share".to_string());
+        wtr.write_record(&header)?;
+
+        for (args, amounts) in &self.amounts {
+            let mut record = vec![
+                Address::new(args.clone()).mainnet_short_format()?,
+                format!("0x{}", faster_hex::hex_string(&args[..]).unwrap()),
+            ];
+            for source in &self.sources {
+                record.push(
+                    amounts
+                        .get(source)
+                        .map(|capacity| format_ckb(*capacity))
+                        .unwrap_or_default(),
+                );
+            }
+            let total = self.total(amounts)?;
+            record.push(format_ckb(total));
+            record.push(format!("{:.8}", self.share(total)));
+            wtr.write_record(&record)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    fn write_json(&self, path: &str) -> Result<(), Error> {
+        let mut rows = Vec::with_capacity(self.amounts.len());
+        for (args, amounts) in &self.amounts {
+            let total = self.total(amounts)?;
+            let by_source: BTreeMap<_, _> = amounts
+                .iter()
+                .map(|(source, capacity)| (source.clone(), format_ckb(*capacity)))
+                .collect();
+            rows.push(json!({
+                "mainnet_address": Address::new(args.clone()).mainnet_short_format()?,
+                "args": format!("0x{}", faster_hex::hex_string(&args[..]).unwrap()),
+                "amounts": by_source,
+                "total": format_ckb(total),
+                "share": self.share(total),
+            }));
+        }
+        let document = json!({
+            "pool": format_ckb(self.pool),
+            "sources": self.sources,
+            "incentives": rows,
+        });
+        fs::write(path, serde_json::to_string_pretty(&document)?)?;
+        Ok(())
+    }
+}
+
+fn format_ckb(capacity: Capacity) -> String {
+    let shannons = capacity.as_u64();
+    format!(
+        "{}.{:08}",
+        shannons / BYTE_SHANNONS,
+        shannons % BYTE_SHANNONS
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::parse_mining_competition_record;
+    use crate::{load_mining_competition_records, MINING_COMPETITION_REWARD};
+
+    fn assert_sums_match(breakdown: &Breakdown, map: &BTreeMap<Bytes, Capacity>) {
+        assert_eq!(
+            breakdown.amounts.keys().collect::<Vec<_>>(),
+            map.keys().collect::<Vec<_>>()
+        );
+        for (args, amounts) in &breakdown.amounts {
+            assert_eq!(breakdown.total(amounts).unwrap(), map[args]);
+        }
+    }
+
+    #[test]
+    fn test_sources_sum_to_aggregated_map() {
+        let mut map = BTreeMap::new();
+        let mut breakdown = Some(Breakdown::new(MINING_COMPETITION_REWARD));
+        load_mining_competition_records(&mut map, &mut None, &mut breakdown);
+        let mut breakdown = breakdown.unwrap();
+        assert_eq!(breakdown.sources.len(), 8);
+        assert_sums_match(&breakdown, &map);
+
+        // stage 3 rewards an existing and a new pubkey hash
+        let before = map.clone();
+        let existing = map.keys().next().unwrap().clone();
+        let reward = Capacity::shannons(123_456_789);
+        let updated = map[&existing].safe_add(reward).unwrap();
+        map.insert(existing.clone(), updated);
+        map.insert(Bytes::from(vec![0xee; 20]), reward);
+        breakdown.add_diff("round5.stage3", &before, &map).unwrap();
+        assert_eq!(breakdown.sources.last().unwrap(), "round5.stage3");
+        assert_eq!(breakdown.amounts[&existing]["round5.stage3"], reward);
+        assert_sums_match(&breakdown, &map);
+    }
+
+    #[test]
+    fn test_skip_invalid_addresses() {
+        let records = vec![
+            RawRecord {
+                address: Address::new(Bytes::from(vec![1u8; 20]))
+                    .testnet_short_format()
+                    .unwrap(),
+                capacity: 10,
+            },
+            RawRecord {
+                address: "ckt1invalid".to_string(),
+                capacity: 20,
+            },
+        ];
+        let mut map = BTreeMap::new();
+        parse_mining_competition_record(records.clone(), &mut map).unwrap();
+        let mut breakdown = Breakdown::new(MINING_COMPETITION_REWARD);
+        breakdown.add_records("round1.csv", records).unwrap();
+        assert_eq!(map.len(), 1);
+        assert_sums_match(&breakdown, &map);
+    }
+}
@@ -2,17 +2,15 @@ use crate::{
     address::Address,
     date::{parse_date, Outset},
     template::IssuedCell,
-    DEFAULT_CODE_HASH, MULTISIG_CODE_HASH,
+    BYTE_SHANNONS, DEFAULT_CODE_HASH, MULTISIG_CODE_HASH,
 };
 use ckb_types::{bytes::Bytes, core::Capacity};
-use failure::Error;
+use failure::{format_err, Error};
 use serde_derive::Deserialize;
 use std::collections::BTreeMap;
 use std::convert::{TryFrom, TryInto};
 use std::io::Read;
 
-const BYTE_SHANNONS: u64 = 100_000_000;
-
 #[derive(Debug, Clone, Deserialize)]
 pub struct RawRecord {
     pub address: String,
@@ -114,14 +112,32 @@ pub fn serialize_multisig_lock_args(
     let address = Address::from_str(address)?;
     let dt = parse_date(date)?;
     let since = Outset.since_epoch(&dt, target);
-    let mut script = Bytes::from(vec![0u8, 0, 1, 1]);
-    script.extend_from_slice(&address.args);
-    let mut args = blake160(&script).to_vec();
+    let mut args = multisig_script_hash(&address.args).to_vec();
 
     args.extend(since.to_le_bytes().iter());
     Ok(Bytes::from(args))
 }
 
+/// blake160 of the 1 of 1 multisig script `S | R | M | N | PubkeyHash`.
+pub fn multisig_script_hash(pubkey_hash: &[u8]) -> Bytes {
+    let mut script = Bytes::from(vec![0u8, 0, 1, 1]);
+    script.extend_from_slice(pubkey_hash);
+    blake160(&script)
+}
+
+/// Splits multisig lock args into the multisig script hash and the since.
+pub fn deserialize_multisig_lock_args(args: &[u8]) -> Result<(Bytes, u64), Error> {
+    if args.len() != 28 {
+        return Err(format_err!(
+            "multisig lock args must be 28 bytes, got {}",
+            args.len()
+        ));
+    }
+    let mut since = [0u8; 8];
+    since.copy_from_slice(&args[20..]);
+    Ok((Bytes::from(&args[..20]), u64::from_le_bytes(since)))
+}
+
 pub fn convert_record_allocate(record: LockRecord, target: u64) -> Result<Allocate, Error> {
     if let Some(ref date) = &record.lock {
         let args = serialize_multisig_lock_args(&record.address, date, target)?;
@@ -139,3 +155,92 @@ pub fn convert_record_allocate(record: LockRecord, target: u64) -> Result<Alloca
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::date::Outset;
+    use ckb_types::core::EpochNumberWithFraction;
+
+    /// 64 fixed pubkey hashes from a xorshift sequence, the same in every run.
+    fn pubkey_hashes() -> impl Iterator<Item = Bytes> {
+        let mut state = 0x2545_f491_4f6c_dd1du64;
+        (0..64).map(move |_| {
+            let hash: Vec<u8> = (0..20)
+                .map(|_| {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    state as u8
+                })
+                .collect();
+            Bytes::from(hash)
+        })
+    }
+
+    #[test]
+    fn test_multisig_lock_args_fixed_samples() {
+        let dates = [
+            "2019-11-17",
+            "2020-01-01",
+            "2020-07-01",
+            "2021-11-16",
+            "2023-05-31",
+        ];
+        for pubkey_hash in pubkey_hashes() {
+            let address = Address::new(pubkey_hash.clone());
+            let mut script = vec![0u8, 0, 1, 1];
+            script.extend_from_slice(&pubkey_hash);
+            let multisig_hash = blake160(&script);
+
+            for format in &[
+                address.mainnet_short_format().unwrap(),
+                address.testnet_short_format().unwrap(),
+            ] {
+                for date in dates.iter() {
+                    for target in (4..=120).step_by(5) {
+                        let args = serialize_multisig_lock_args(format, date, target).unwrap();
+                        assert_eq!(args.len(), 28);
+                        assert_eq!(&args[..20], &multisig_hash[..]);
+
+                        let mut since = [0u8; 8];
+                        since.copy_from_slice(&args[20..]);
+                        let since = u64::from_le_bytes(since);
+                        assert_eq!(
+                            since,
+                            Outset.since_epoch(&parse_date(date).unwrap(), target)
+                        );
+                        assert_eq!(args[27], 0x20, "since flag");
+
+                        let epoch =
+                            EpochNumberWithFraction::from_full_value(since & 0x00ff_ffff_ffff_ffff);
+                        assert_eq!(epoch.length(), 1800);
+                        assert_eq!(
+                            u64::from(args[20])
+                                | u64::from(args[21]) << 8
+                                | u64::from(args[22]) << 16,
+                            epoch.number()
+                        );
+                        assert_eq!(
+                            u64::from(args[23]) | u64::from(args[24]) << 8,
+                            epoch.index()
+                        );
+                        assert_eq!(
+                            u64::from(args[25]) | u64::from(args[26]) << 8,
+                            epoch.length()
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_multisig_lock_args_rejects_invalid_input() {
+        let address = Address::new(Bytes::from(vec![7u8; 20]))
+            .mainnet_short_format()
+            .unwrap();
+        assert!(serialize_multisig_lock_args(&address, "2020-13-01", 89).is_err());
+        assert!(serialize_multisig_lock_args("ckb1invalid", "2020-07-01", 89).is_err());
+    }
+}
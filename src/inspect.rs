@@ -0,0 +1,183 @@
+use crate::address::Address;
+use crate::date::{decode_since, Outset};
+use crate::input::{deserialize_multisig_lock_args, multisig_script_hash};
+use crate::{DEFAULT_CODE_HASH, MULTISIG_CODE_HASH};
+use ckb_chain_spec::ChainSpec;
+use ckb_jsonrpc_types::{Script, ScriptHashType};
+use ckb_types::{bytes::Bytes, H256};
+use failure::{format_err, Error};
+use std::fs;
+
+/// Reads the lock of the `index`th issued cell in a generated spec.
+pub fn issued_cell_lock(path: &str, index: usize) -> Result<Script, Error> {
+    let spec: ChainSpec = toml::from_str(&fs::read_to_string(path)?)?;
+    let cell = spec
+        .genesis
+        .issued_cells
+        .get(index)
+        .ok_or_else(|| format_err!("{} has no issued cell {}", path, index))?;
+    Ok(cell.lock.clone())
+}
+
+#[derive(Debug, PartialEq)]
+enum LockKind {
+    Sighash,
+    Multisig,
+    /// The zero code hash, no cell can ever unlock it.
+    Burned,
+    Unknown,
+}
+
+fn lock_kind(lock: &Script) -> LockKind {
+    let code_hash = format!("{:#x}", lock.code_hash);
+    let by_type = lock.hash_type == ScriptHashType::Type;
+    if code_hash == DEFAULT_CODE_HASH && by_type {
+        LockKind::Sighash
+    } else if code_hash == MULTISIG_CODE_HASH && by_type {
+        LockKind::Multisig
+    } else if lock.code_hash == H256::zero() {
+        LockKind::Burned
+    } else {
+        LockKind::Unknown
+    }
+}
+
+pub fn parse_args(input: &str) -> Result<Bytes, Error> {
+    let hex = input.trim_start_matches("0x");
+    if hex.len() % 2 != 0 {
+        return Err(format_err!("invalid args {}", input));
+    }
+    let mut args = vec![0u8; hex.len() / 2];
+    faster_hex::hex_decode(hex.as_bytes(), &mut args)
+        .map_err(|_| format_err!("invalid args {}", input))?;
+    Ok(Bytes::from(args))
+}
+
+/// Prints what a genesis cell lock means, and who owns it if it matches one of
+/// the `candidates`. Without the whole `lock`, only the `args` given on the
+/// command line, the lock is assumed from the args length.
+pub fn inspect_args(
+    lock: Option<&Script>,
+    args: &[u8],
+    target: u64,
+    candidates: &[Address],
+) -> Result<(), Error> {
+    println!("args: 0x{}", faster_hex::hex_string(args).unwrap());
+    let kind = match lock {
+        Some(lock) => lock_kind(lock),
+        None => {
+            println!("lock: assumed from the args length, pass --cell to read it from the spec");
+            match args.len() {
+                20 => LockKind::Sighash,
+                28 => LockKind::Multisig,
+                len => return Err(format_err!("unknown lock args length {}", len)),
+            }
+        }
+    };
+    match kind {
+        LockKind::Burned => {
+            println!("lock: zero code hash");
+            println!("unlock: never, the cell is burned");
+            Ok(())
+        }
+        LockKind::Unknown => {
+            let lock = lock.expect("unknown only with a lock");
+            println!(
+                "lock: unknown, code hash {:#x} ({:?})",
+                lock.code_hash, lock.hash_type
+            );
+            println!("unlock: unknown");
+            Ok(())
+        }
+        LockKind::Sighash if args.len() != 20 => Err(format_err!(
+            "sighash lock args length {}, expected 20",
+            args.len()
+        )),
+        LockKind::Sighash => {
+            println!("lock: secp256k1_blake160_sighash_all");
+            println!("unlock: no time lock");
+            let owner = candidates.iter().find(|address| &address.args[..] == args);
+            print_owner(owner, candidates)
+        }
+        LockKind::Multisig => {
+            let (hash, since) = deserialize_multisig_lock_args(args)?;
+            let (flag, epoch) = decode_since(since);
+            println!("lock: secp256k1_blake160_multisig_all");
+            println!(
+                "multisig script hash: 0x{}",
+                faster_hex::hex_string(&hash).unwrap()
+            );
+            println!("since: {:#018x}", since);
+            println!(
+                "  flag: {:#04x} ({})",
+                flag,
+                if flag == 0x20 {
+                    "absolute epoch"
+                } else {
+                    "unexpected"
+                }
+            );
+            println!(
+                "  epoch: {} + {}/{}",
+                epoch.number(),
+                epoch.index(),
+                epoch.length()
+            );
+            match Outset.date_of_since(since, target) {
+                Some(date) => println!(
+                    "unlock: around {} (target epoch {})",
+                    date.format("%Y-%m-%d %H:%M:%S UTC"),
+                    target
+                ),
+                None => println!(
+                    "unlock: clamped to the launch, lock date is before epoch {} in testnet",
+                    target
+                ),
+            }
+            let owner = candidates
+                .iter()
+                .find(|address| multisig_script_hash(&address.args) == hash);
+            print_owner(owner, candidates)
+        }
+    }
+}
+
+fn print_owner(owner: Option<&Address>, candidates: &[Address]) -> Result<(), Error> {
+    match owner {
+        Some(address) => println!("owner: {}", address.mainnet_short_format()?),
+        None if !candidates.is_empty() => println!("owner: none of the candidates"),
+        None => {}
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn lock(code_hash: &str, hash_type: ScriptHashType) -> Script {
+        Script {
+            code_hash: H256::from_str(&code_hash[2..]).unwrap(),
+            hash_type,
+            args: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_lock_kind() {
+        let sighash = lock(DEFAULT_CODE_HASH, ScriptHashType::Type);
+        assert_eq!(lock_kind(&sighash), LockKind::Sighash);
+        let multisig = lock(MULTISIG_CODE_HASH, ScriptHashType::Type);
+        assert_eq!(lock_kind(&multisig), LockKind::Multisig);
+        // the burned cell 0 of the spec
+        let burned = Script {
+            code_hash: H256::zero(),
+            hash_type: ScriptHashType::Data,
+            args: Default::default(),
+        };
+        assert_eq!(lock_kind(&burned), LockKind::Burned);
+        let by_data = lock(DEFAULT_CODE_HASH, ScriptHashType::Data);
+        assert_eq!(lock_kind(&by_data), LockKind::Unknown);
+    }
+}
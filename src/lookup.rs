@@ -0,0 +1,242 @@
+use crate::address::Address;
+use crate::genesis::issued_cell_out_points;
+use crate::input::serialize_multisig_lock_args;
+use crate::{BYTE_SHANNONS, DEFAULT_CODE_HASH, MULTISIG_CODE_HASH};
+use ckb_chain_spec::ChainSpec;
+use ckb_jsonrpc_types::ScriptHashType;
+use ckb_types::{core::Capacity, packed::Byte32};
+use failure::{format_err, Error};
+use std::fs;
+
+/// Partitions of the issued cells, in the order they appear in the spec.
+pub struct Sections {
+    /// Number of cells generated from `genesis_final.csv`.
+    pub allocate: usize,
+}
+
+impl Sections {
+    fn source(&self, index: usize, len: usize) -> &'static str {
+        if index == 0 {
+            "burn"
+        } else if index <= self.allocate {
+            "allocate"
+        } else if index == self.allocate + 1 {
+            "foundation reserve"
+        } else if index == len - 1 {
+            "testnet incentives remainder"
+        } else {
+            "testnet incentives"
+        }
+    }
+}
+
+pub struct OwnedCell {
+    pub index: usize,
+    pub out_point_index: u32,
+    pub capacity: Capacity,
+    pub unlock: Option<String>,
+    pub source: &'static str,
+}
+
+/// Finds every issued cell in the spec at `path` owned by `address`.
+///
+/// Time locked cells are matched by recomputing the multisig lock args for each
+/// of `lock_dates` with `target`.
+pub fn lookup(
+    path: &str,
+    address: &Address,
+    lock_dates: &[String],
+    target: u64,
+    sections: &Sections,
+) -> Result<(Byte32, Vec<OwnedCell>), Error> {
+    let spec: ChainSpec = toml::from_str(&fs::read_to_string(path)?)?;
+    let consensus = spec
+        .build_consensus()
+        .map_err(|e| format_err!("build consensus: {}", e))?;
+    let (tx_hash, out_points) = issued_cell_out_points(&spec, &consensus)?;
+
+    let mainnet_address = address.mainnet_short_format()?;
+    let mut timelocks = Vec::with_capacity(lock_dates.len());
+    for date in lock_dates {
+        timelocks.push((
+            serialize_multisig_lock_args(&mainnet_address, date, target)?,
+            date,
+        ));
+    }
+
+    let len = spec.genesis.issued_cells.len();
+    let cells = spec
+        .genesis
+        .issued_cells
+        .iter()
+        .enumerate()
+        .filter_map(|(index, cell)| {
+            let code_hash = format!("{:#x}", cell.lock.code_hash);
+            let args = cell.lock.args.as_bytes();
+            // both scripts are referenced by type id, a data hash lock is someone else's
+            if cell.lock.hash_type != ScriptHashType::Type {
+                return None;
+            }
+            let unlock = if code_hash == DEFAULT_CODE_HASH && args == &address.args[..] {
+                None
+            } else if code_hash == MULTISIG_CODE_HASH {
+                let (_, date) = timelocks
+                    .iter()
+                    .find(|(lock_args, _)| &lock_args[..] == args)?;
+                Some(date.to_string())
+            } else {
+                return None;
+            };
+            Some(OwnedCell {
+                index,
+                out_point_index: out_points[index],
+                capacity: cell.capacity,
+                unlock,
+                source: sections.source(index, len),
+            })
+        })
+        .collect();
+    Ok((tx_hash, cells))
+}
+
+pub fn print_cells(tx_hash: &Byte32, cells: &[OwnedCell]) {
+    println!("genesis cellbase: {:#x}", tx_hash);
+    println!(
+        "{:>6} {:>10} {:>20} {:>12} source",
+        "cell", "out point", "capacity (CKB)", "unlock"
+    );
+    for cell in cells {
+        println!(
+            "{:>6} {:>10} {:>20} {:>12} {}",
+            cell.index,
+            cell.out_point_index,
+            cell.capacity.as_u64() / BYTE_SHANNONS,
+            cell.unlock.as_ref().map(String::as_str).unwrap_or("-"),
+            cell.source
+        );
+    }
+    let total = cells.iter().map(|cell| cell.capacity.as_u64()).sum::<u64>();
+    println!(
+        "total: {} cells, {} CKB",
+        cells.len(),
+        total / BYTE_SHANNONS
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::{IssuedCell, Spec};
+    use crate::TEMPLATE;
+    use ckb_types::{packed::Script, prelude::*};
+    use std::{env, process};
+    use tinytemplate::TinyTemplate;
+
+    const ADDRESS: &str = "ckb1qyq9xcl8cg8supmzzy0szazepu89832xq2ts070xnk";
+    const OTHER: &str = "ckb1qyqy6mtud5sgctjwgg6gydd0ea05mr339lnslczzrc";
+    const TARGET: u64 = 89;
+
+    fn sighash(address: &str, ckb: u64) -> IssuedCell {
+        let address = Address::from_str(address).unwrap();
+        IssuedCell {
+            capacity: ckb * BYTE_SHANNONS,
+            code_hash: DEFAULT_CODE_HASH.to_string(),
+            args: format!("0x{}", faster_hex::hex_string(&address.args).unwrap()),
+        }
+    }
+
+    fn multisig(address: &str, date: &str, ckb: u64) -> IssuedCell {
+        let args = serialize_multisig_lock_args(address, date, TARGET).unwrap();
+        IssuedCell {
+            capacity: ckb * BYTE_SHANNONS,
+            code_hash: MULTISIG_CODE_HASH.to_string(),
+            args: format!("0x{}", faster_hex::hex_string(&args).unwrap()),
+        }
+    }
+
+    fn render(context: &Spec) -> String {
+        let mut tt = TinyTemplate::new();
+        tt.add_template("test", TEMPLATE).unwrap();
+        tt.render("test", context).unwrap()
+    }
+
+    #[test]
+    fn test_lookup_owned_cells() {
+        let data_hash = sighash(ADDRESS, 4_000);
+        let data_hash_lock = format!(
+            "capacity = {}\nlock.code_hash = \"{}\"\nlock.args = \"{}\"\nlock.hash_type = ",
+            data_hash.capacity, data_hash.code_hash, data_hash.args
+        );
+        let context = Spec {
+            timestamp: 0,
+            compact_target: "0x20ffffff".to_string(),
+            message: "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+            epoch_length: 1000,
+            allocate: vec![
+                sighash(OTHER, 1_000),
+                sighash(ADDRESS, 2_000),
+                multisig(ADDRESS, "2021-01-01", 3_000),
+                data_hash,
+            ],
+            foundation_reserve: Some(multisig(ADDRESS, "2020-07-01", 5_000)),
+            testnet_incentives: vec![sighash(ADDRESS, 6_000), sighash(OTHER, 7_000)],
+        };
+        // the same sighash args, but the lock references the script by data hash
+        let rendered = render(&context).replace(
+            &format!("{}\"type\"", data_hash_lock),
+            &format!("{}\"data\"", data_hash_lock),
+        );
+        let path = env::temp_dir().join(format!("ckb-gbg-lookup-{}.toml", process::id()));
+        fs::write(&path, &rendered).unwrap();
+
+        let lock_dates = vec!["2020-07-01".to_string(), "2021-01-01".to_string()];
+        let sections = Sections { allocate: 4 };
+        let (tx_hash, cells) = lookup(
+            path.to_str().unwrap(),
+            &Address::from_str(ADDRESS).unwrap(),
+            &lock_dates,
+            TARGET,
+            &sections,
+        )
+        .unwrap();
+
+        let spec: ChainSpec = toml::from_str(&rendered).unwrap();
+        fs::remove_file(&path).unwrap();
+        let consensus = spec.build_consensus().unwrap();
+        let cellbase = &consensus.genesis_block().transactions()[0];
+        assert_eq!(tx_hash, cellbase.hash());
+
+        let owned: Vec<_> = cells
+            .iter()
+            .map(|cell| {
+                (
+                    cell.index,
+                    cell.unlock.as_ref().map(String::as_str),
+                    cell.source,
+                )
+            })
+            .collect();
+        assert_eq!(
+            owned,
+            vec![
+                (2, None, "allocate"),
+                (3, Some("2021-01-01"), "allocate"),
+                (5, Some("2020-07-01"), "foundation reserve"),
+                (6, None, "testnet incentives"),
+            ]
+        );
+
+        let outputs: Vec<_> = cellbase.outputs().into_iter().collect();
+        for cell in &cells {
+            let issued = &spec.genesis.issued_cells[cell.index];
+            let output = &outputs[cell.out_point_index as usize];
+            let lock: Script = issued.lock.clone().into();
+            assert_eq!(output.lock(), lock);
+            assert_eq!(
+                Unpack::<Capacity>::unpack(&output.capacity()),
+                cell.capacity
+            );
+            assert_eq!(cell.capacity, issued.capacity);
+        }
+    }
+}
@@ -1,29 +1,46 @@
 mod address;
 mod date;
+mod estimate;
 mod explorer;
+mod genesis;
+mod incentives;
 mod input;
+mod inspect;
+mod lookup;
 mod output;
 mod rpc;
+mod simulate;
+mod source;
 mod template;
 
 use crate::address::Address;
-use ckb_chain_spec::ChainSpec;
+use ckb_chain_spec::{consensus::Consensus, ChainSpec};
 use ckb_types::{
     bytes::Bytes,
     core::{capacity_bytes, Capacity},
+    H256,
 };
-use clap::{load_yaml, value_t, App};
-use explorer::Explorer;
+use clap::{load_yaml, value_t, App, ArgMatches};
+use explorer::{
+    Explorer, Options, RedirectedBlock, ReorgError, Rounding, DEFAULT_CROSS_CHECK_INTERVAL,
+    METRIC_EPOCH,
+};
+use genesis::issued_cell_out_points;
+use incentives::Breakdown;
 use input::{
     collect_allocate, parse_mining_competition_record, read_allocate,
-    read_mining_competition_record, serialize_multisig_lock_args,
+    read_mining_competition_record, serialize_multisig_lock_args, LockRecord,
 };
-use output::{write_allocate_output, write_incentives_output};
+use output::{write_allocate_output, write_computed_output, write_incentives_output, Audit};
+use rpc::{HttpOptions, HttpTransport, RecordTransport, ReplayTransport, RpcClient};
 use sha2::{Digest, Sha256};
+use source::{CachedSource, ChainSource, ExportSource, DEFAULT_SAFE_DEPTH};
 use std::collections::BTreeMap;
-use std::fs::{self, File};
+use std::fs;
 use std::io::BufReader;
+use std::path::Path;
 use std::process::exit;
+use std::str::FromStr;
 use template::{IssuedCell, Spec};
 use tinytemplate::TinyTemplate;
 
@@ -39,55 +56,480 @@ const INCENTIVES_ADDRESS: &str = "ckb1qyqy6mtud5sgctjwgg6gydd0ea05mr339lnslczzrc
 const FOUNDATION_ADDRESS: &str = "ckb1qyqyz340d4nhgtx2s75mp5wnavrsu7j5fcwqktprrp";
 const FOUNDATION_LOCK: &str = "2020-07-01";
 const INITIAL_ISSUES: Capacity = capacity_bytes!(33_600_000_000);
+const BYTE_SHANNONS: u64 = 100_000_000;
 
 fn main() {
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
-    let url = matches
-        .value_of("url")
-        .unwrap_or_else(|| "http://localhost:8114");
+    match matches.subcommand() {
+        ("estimate", Some(sub_matches)) => estimate(sub_matches),
+        ("inspect-args", Some(sub_matches)) => inspect_args(sub_matches),
+        ("lookup", Some(sub_matches)) => lookup(sub_matches),
+        ("simulate", Some(sub_matches)) => simulate(sub_matches),
+        _ => generate(&matches),
+    }
+}
+
+fn explorer_options(matches: &ArgMatches) -> Options {
+    Options {
+        confirmations: value_t!(matches, "confirmations", u64).unwrap_or(0),
+        cross_check_interval: value_t!(matches, "cross-check-interval", u64)
+            .unwrap_or(DEFAULT_CROSS_CHECK_INTERVAL),
+        verify_rewards: matches.is_present("verify-rewards"),
+        finalization_delay: value_t!(matches, "finalization-delay", u64).ok(),
+        genesis_hash: matches.value_of("genesis-hash").map(|hash| {
+            H256::from_str(hash.trim_start_matches("0x")).unwrap_or_else(|e| {
+                eprintln!("invalid genesis hash {}: {}", hash, e);
+                exit(1);
+            })
+        }),
+    }
+}
+
+fn rounding(matches: &ArgMatches) -> Rounding {
+    if matches.is_present("largest-remainder") {
+        Rounding::LargestRemainder
+    } else {
+        Rounding::Floor
+    }
+}
+
+/// Reads the chain from `--import` or `--data-dir` if given, cross-checked
+/// against the `--url` endpoints, otherwise from the first `--url`.
+fn explorer(matches: &ArgMatches, target: u64, options: Options) -> Explorer {
+    let urls = urls(matches);
+    let http = http_options(matches);
+    let explicit: &[&str] = if matches.is_present("url") {
+        &urls
+    } else {
+        &[]
+    };
+    let (source, peer_urls): (Box<dyn ChainSource>, &[&str]) =
+        if let Some(path) = matches.value_of("import") {
+            let source = ExportSource::open(path).unwrap_or_else(|e| {
+                eprintln!("import error: {}", e);
+                exit(1);
+            });
+            (Box::new(source), explicit)
+        } else if let Some(path) = matches.value_of("data-dir") {
+            (open_data_dir(path), explicit)
+        } else {
+            (
+                cached(matches, rpc_client(matches, urls[0], &http)),
+                &urls[1..],
+            )
+        };
+    let peers = peer_urls
+        .iter()
+        .map(|url| Box::new(http_client(url, &http)) as Box<dyn ChainSource>)
+        .collect();
+    Explorer::with_sources(source, peers, target, options)
+}
+
+/// Keeps the immutable responses of `client` in `--cache` if given.
+fn cached(matches: &ArgMatches, client: RpcClient) -> Box<dyn ChainSource> {
+    match matches.value_of("cache") {
+        Some(dir) => Box::new(
+            CachedSource::open(Box::new(client), dir, DEFAULT_SAFE_DEPTH).unwrap_or_else(|e| {
+                eprintln!("cache error: {}: {}", dir, e);
+                exit(1);
+            }),
+        ),
+        None => Box::new(client),
+    }
+}
+
+/// Reads the endpoint settings from `--rpc-config`, the command line adds
+/// headers and overrides the other settings.
+fn http_options(matches: &ArgMatches) -> HttpOptions {
+    let mut options: HttpOptions = matches
+        .value_of("rpc-config")
+        .map(|path| {
+            fs::read_to_string(path)
+                .map_err(Into::into)
+                .and_then(|content| toml::from_str(&content).map_err(Into::<failure::Error>::into))
+                .unwrap_or_else(|e| {
+                    eprintln!("rpc config error: {}: {}", path, e);
+                    exit(1);
+                })
+        })
+        .unwrap_or_default();
+    if let Some(headers) = matches.values_of("header") {
+        options.headers.extend(headers.map(ToString::to_string));
+    }
+    if let Some(path) = matches.value_of("ca-bundle") {
+        options.ca_bundle = Some(path.to_string());
+    }
+    if let Some(path) = matches.value_of("client-cert") {
+        options.client_cert = Some(path.to_string());
+    }
+    if let Some(password) = matches.value_of("client-cert-password") {
+        options.client_cert_password = Some(password.to_string());
+    }
+    if let Ok(timeout) = value_t!(matches, "rpc-timeout", u64) {
+        options.timeout = Some(timeout);
+    }
+    if let Some(proxy) = matches.value_of("proxy") {
+        options.proxy = Some(proxy.to_string());
+    }
+    options
+}
+
+fn http_client(url: &str, options: &HttpOptions) -> RpcClient {
+    RpcClient::with_http(url, options).unwrap_or_else(|e| {
+        eprintln!("rpc endpoint error: {}", e);
+        exit(1);
+    })
+}
+
+/// `url` without credentials, to name an endpoint that is not connected to.
+fn redact(url: &str) -> String {
+    match reqwest::Url::parse(url) {
+        Ok(mut parsed) => {
+            let _ = parsed.set_username("");
+            let _ = parsed.set_password(None);
+            parsed.to_string()
+        }
+        Err(_) => url.to_string(),
+    }
+}
+
+/// Connects to `url`, or serves its responses from `--replay-rpc` and records
+/// them to `--record-rpc`.
+fn rpc_client(matches: &ArgMatches, url: &str, options: &HttpOptions) -> RpcClient {
+    if let Some(path) = matches.value_of("replay-rpc") {
+        let transport = ReplayTransport::open(path).unwrap_or_else(|e| {
+            eprintln!("replay error: {}", e);
+            exit(1);
+        });
+        RpcClient::with_transport(&redact(url), Box::new(transport))
+    } else if let Some(path) = matches.value_of("record-rpc") {
+        let http = reqwest::Url::parse(url)
+            .map_err(Into::into)
+            .and_then(|url| HttpTransport::new(url, options))
+            .unwrap_or_else(|e| {
+                eprintln!("rpc endpoint error: {}", e);
+                exit(1);
+            });
+        let name = http.url().to_string();
+        let transport = RecordTransport::create(http, path).unwrap_or_else(|e| {
+            eprintln!("record error: {}", e);
+            exit(1);
+        });
+        RpcClient::with_transport(&name, Box::new(transport))
+    } else {
+        http_client(url, options)
+    }
+}
+
+#[cfg(feature = "db")]
+fn open_data_dir(path: &str) -> Box<dyn ChainSource> {
+    Box::new(source::DbSource::open(path).unwrap_or_else(|e| {
+        eprintln!("data dir error: {}", e);
+        exit(1);
+    }))
+}
+
+#[cfg(not(feature = "db"))]
+fn open_data_dir(_path: &str) -> Box<dyn ChainSource> {
+    eprintln!("--data-dir requires building with --features db");
+    exit(1);
+}
+
+fn urls<'a>(matches: &'a ArgMatches) -> Vec<&'a str> {
+    matches
+        .values_of("url")
+        .map(|values| values.collect())
+        .unwrap_or_else(|| vec!["http://localhost:8114"])
+}
+
+fn estimate(matches: &ArgMatches) {
     let target = value_t!(matches, "target", u64).unwrap_or(DEFAULT_TARGET_EPOCH);
+    let window = value_t!(matches, "window", u64).unwrap_or(METRIC_EPOCH);
+
+    let explorer = explorer(matches, target, explorer_options(matches));
+    let estimate = explorer
+        .tip_header()
+        .and_then(|tip_header| explorer.estimate(&tip_header, window))
+        .unwrap_or_else(|e| {
+            eprintln!("explorer error: {}", e);
+            exit(1);
+        });
 
-    let mut output = matches.value_of("output").map(|path| {
-        csv::Writer::from_path(path).unwrap_or_else(|e| {
+    if matches.is_present("json") {
+        estimate.print_json().unwrap_or_else(|e| {
+            eprintln!("estimate json: {}", e);
+            exit(1);
+        });
+    } else {
+        estimate.print();
+    }
+}
+
+fn inspect_args(matches: &ArgMatches) {
+    let target = value_t!(matches, "target", u64).unwrap_or(DEFAULT_TARGET_EPOCH);
+    let (lock, args) = match matches.value_of("args") {
+        Some(args) => inspect::parse_args(args).map(|args| (None, args)),
+        None => {
+            let spec = matches.value_of("spec").unwrap_or("lina.toml");
+            let index = value_t!(matches, "cell", usize).unwrap_or_else(|e| e.exit());
+            inspect::issued_cell_lock(spec, index).map(|lock| {
+                let args = lock.args.clone().into_bytes();
+                (Some(lock), args)
+            })
+        }
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("inspect error: {}", e);
+        exit(1);
+    });
+    let candidates: Vec<Address> = matches
+        .values_of("address")
+        .map(|values| values.collect())
+        .unwrap_or_else(Vec::new)
+        .into_iter()
+        .map(|address| {
+            Address::from_str(address).unwrap_or_else(|e| {
+                eprintln!("inspect error: {}", e);
+                exit(1);
+            })
+        })
+        .collect();
+
+    inspect::inspect_args(lock.as_ref(), &args, target, &candidates).unwrap_or_else(|e| {
+        eprintln!("inspect error: {}", e);
+        exit(1);
+    });
+}
+
+fn lookup(matches: &ArgMatches) {
+    let target = value_t!(matches, "target", u64).unwrap_or(DEFAULT_TARGET_EPOCH);
+    let spec = matches.value_of("spec").unwrap_or("lina.toml");
+    let address = matches
+        .value_of("address")
+        .map(Address::from_str)
+        .expect("required address")
+        .unwrap_or_else(|e| {
+            eprintln!("lookup error: {}", e);
+            exit(1);
+        });
+
+    let records = allocate_records();
+    let mut lock_dates: Vec<String> = records
+        .iter()
+        .filter_map(|record| record.lock.clone())
+        .chain(Some(FOUNDATION_LOCK.to_string()))
+        .collect();
+    lock_dates.sort();
+    lock_dates.dedup();
+    let sections = lookup::Sections {
+        allocate: collect_allocate(records, target).len(),
+    };
+
+    let (tx_hash, cells) = lookup::lookup(spec, &address, &lock_dates, target, &sections)
+        .unwrap_or_else(|e| {
+            eprintln!("lookup error: {}", e);
+            exit(1);
+        });
+    lookup::print_cells(&tx_hash, &cells);
+}
+
+fn simulate(matches: &ArgMatches) {
+    let target = value_t!(matches, "target", u64).unwrap_or(DEFAULT_TARGET_EPOCH);
+    let first = value_t!(matches, "first", u64).unwrap_or(target);
+    if first < 4 || first > target {
+        eprintln!("first epoch must be larger than 3 and not after the target");
+        exit(1);
+    }
+
+    let scan = match matches.value_of("load") {
+        Some(path) => simulate::load_scan(path),
+        None => explorer(matches, target, explorer_options(matches)).scan(first),
+    }
+    .unwrap_or_else(|e| {
+        eprintln!("scan error: {}", e);
+        exit(1);
+    });
+    if let Some(path) = matches.value_of("save") {
+        simulate::save_scan(path, &scan).unwrap_or_else(|e| {
+            eprintln!("save scan: {}", e);
+            exit(1);
+        });
+    }
+
+    let results = (first..=target)
+        .map(|target| scan.stage3(target, rounding(matches)))
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap_or_else(|e| {
+            eprintln!("simulate error: {}", e);
+            exit(1);
+        });
+    simulate::print_table(&results);
+    if let Some(path) = matches.value_of("rewards") {
+        simulate::write_rewards(path, &results).unwrap_or_else(|e| {
+            eprintln!("rewards report: {}", e);
+            exit(1);
+        });
+    }
+}
+
+fn generate(matches: &ArgMatches) {
+    let target = value_t!(matches, "target", u64).unwrap_or(DEFAULT_TARGET_EPOCH);
+    let options = explorer_options(matches);
+
+    let output_path = matches.value_of("output");
+    let split_output = matches.is_present("split-output");
+    // create the output early to fail before the long scan
+    let mut output_writer = output_path.and_then(|path| {
+        let created = if split_output {
+            fs::create_dir_all(path).map(|_| None).map_err(Into::into)
+        } else {
+            csv::Writer::from_path(path).map(Some)
+        };
+        created.unwrap_or_else(|e: csv::Error| {
             eprintln!("create output file failed {}", e);
             exit(1);
         })
     });
+    let mut output = output_path.map(|_| Audit::default());
+    let incentives_path = matches.value_of("incentives");
+    let mut breakdown = incentives_path.map(|_| Breakdown::new(MINING_COMPETITION_REWARD));
 
     if target < 4 {
         eprintln!("target epoch must be larger than 3");
         exit(1);
     }
 
+    let wait = matches.is_present("wait");
+    let explorer = explorer(matches, target, options.clone());
+
     let verbose = matches.is_present("verbose");
     if verbose {
-        println!("url = {}", url);
+        // the source names leave out the credentials of the urls
+        let mut names = explorer.source_names().into_iter();
+        println!("source = {}", names.next().expect("a source"));
+        println!("peers = {}", names.collect::<Vec<_>>().join(", "));
         println!("target = {}", target);
+        println!("confirmations = {}", options.confirmations);
+        println!("cross check interval = {}", options.cross_check_interval);
+        println!("verify rewards = {}", options.verify_rewards);
+        if let Some(delay) = options.finalization_delay {
+            println!("finalization delay = {}", delay);
+        }
     }
 
-    let foundation_reserve = foundation_reserve(target);
-    let allocate = reduce_allocate(target, &mut output);
+    let (rendered, consensus) = generate_spec(
+        &explorer,
+        target,
+        wait,
+        matches.value_of("compact-target"),
+        rounding(matches),
+        &mut output,
+        &mut breakdown,
+    );
 
-    let mut records = BTreeMap::new();
-    load_mining_competition_records(&mut records, &mut output);
-    let explorer = Explorer::new(url, target);
-    let (timestamp, compact_target, message, epoch_length) = explorer
-        .collect(&mut records, &mut output)
-        .unwrap_or_else(|e| {
-            eprintln!("explorer error: {}", e);
+    let issued = consensus.genesis_block().transactions()[0]
+        .outputs_capacity()
+        .unwrap();
+    if verbose {
+        println!("issued = {}", issued);
+        println!("hash = {:#x}", consensus.genesis_block().hash());
+    }
+    assert_eq!(
+        issued, INITIAL_ISSUES,
+        "initial issued must be 33_600_000_000"
+    );
+
+    let spec: ChainSpec = toml::from_str(&rendered).unwrap();
+    let (tx_hash, out_points) = issued_cell_out_points(&spec, &consensus).unwrap_or_else(|e| {
+        eprintln!("genesis out points: {}", e);
+        exit(1);
+    });
+    if let (Some(audit), Some(path)) = (output.as_mut(), output_path) {
+        audit.link_out_points(&spec, &tx_hash, &out_points);
+        let written = match output_writer.as_mut() {
+            Some(wtr) => audit.write(wtr),
+            None => audit.write_split(Path::new(path)),
+        };
+        written.unwrap_or_else(|e| {
+            eprintln!("output flush: {}", e);
             exit(1);
         });
-    let testnet_incentives = reduce_mining_competition_records(records);
+    }
+
+    if let (Some(breakdown), Some(path)) = (breakdown.as_ref(), incentives_path) {
+        breakdown.write(path).unwrap_or_else(|e| {
+            eprintln!("incentives report: {}", e);
+            exit(1);
+        });
+    }
+
+    write_file(rendered);
+    println!("genesis cellbase: {:#x}", tx_hash);
+}
+
+fn generate_spec(
+    explorer: &Explorer,
+    target: u64,
+    wait: bool,
+    compact_target_override: Option<&str>,
+    rounding: Rounding,
+    output: &mut Option<Audit>,
+    breakdown: &mut Option<Breakdown>,
+) -> (String, Consensus) {
+    let foundation_reserve = foundation_reserve(target);
+    let allocate = reduce_allocate(target, output);
+    if let Some(audit) = output.as_mut() {
+        let record = LockRecord {
+            address: FOUNDATION_ADDRESS.to_string(),
+            capacity: foundation_reserve.capacity / BYTE_SHANNONS,
+            lock: Some(FOUNDATION_LOCK.to_string()),
+        };
+        write_computed_output(audit, "foundation", record, target).unwrap();
+    }
+
+    let mut records = BTreeMap::new();
+    load_mining_competition_records(&mut records, output, breakdown);
+    let before_stage3 = records.clone();
+    // collect only touches records and output after the scanned range is verified,
+    // so it is safe to restart the scan after a reorg.
+    let stage3 = loop {
+        if wait {
+            explorer.wait().unwrap_or_else(|e| {
+                eprintln!("explorer error: {}", e);
+                exit(1);
+            });
+        }
+        match explorer.collect(&mut records, output, rounding) {
+            Ok(collected) => break collected,
+            Err(ref e) if wait && e.downcast_ref::<ReorgError>().is_some() => {
+                eprintln!("explorer error: {}, restarting", e);
+            }
+            Err(e) => {
+                eprintln!("explorer error: {}", e);
+                exit(1);
+            }
+        }
+    };
+    println!(
+        "round 5 stage 3 rounding loss: {} CKB, added to the testnet incentives remainder",
+        stage3.rounding_loss.as_u64() / BYTE_SHANNONS
+    );
+    print_redirected(&stage3.redirected);
+    if let Some(breakdown) = breakdown.as_mut() {
+        breakdown
+            .add_diff("round5.stage3", &before_stage3, &records)
+            .unwrap();
+    }
+    let testnet_incentives = reduce_mining_competition_records(records, output);
 
     let context = Spec {
-        timestamp,
-        compact_target: matches
-            .value_of("compact-target")
+        timestamp: stage3.timestamp,
+        compact_target: compact_target_override
             .map(String::from)
-            .unwrap_or_else(|| format!("0x{:x}", compact_target)),
-        message: format!("{:x}", message),
-        epoch_length,
+            .unwrap_or_else(|| format!("0x{:x}", stage3.compact_target)),
+        message: format!("{:x}", stage3.anchor_hash),
+        epoch_length: stage3.epoch_length,
         allocate,
         foundation_reserve: Some(foundation_reserve),
         testnet_incentives,
@@ -100,26 +542,41 @@ fn main() {
     let spec: ChainSpec = toml::from_str(&rendered).unwrap();
     let consensus = spec.build_consensus().unwrap();
 
-    let issued = consensus.genesis_block().transactions()[0]
-        .outputs_capacity()
-        .unwrap();
-    if verbose {
-        println!("issued = {}", issued);
-        println!("hash = {:#x}", consensus.genesis_block().hash());
+    (rendered, consensus)
+}
+
+fn print_redirected(redirected: &[RedirectedBlock]) {
+    if redirected.is_empty() {
+        return;
     }
-    assert_eq!(
-        issued, INITIAL_ISSUES,
-        "initial issued must be 33_600_000_000"
+    let malformed = redirected
+        .iter()
+        .filter(|block| block.lock.is_none())
+        .count();
+    println!(
+        "{} blocks rewarded to {}, {} with invalid locks and {} with malformed cellbase witnesses:",
+        redirected.len(),
+        INCENTIVES_ADDRESS,
+        redirected.len() - malformed,
+        malformed
     );
-
-    if let Some(wtr) = output.as_mut() {
-        wtr.flush().unwrap_or_else(|e| {
-            eprintln!("output flush: {}", e);
-            exit(1);
-        });
+    for block in redirected {
+        let lock = match &block.lock {
+            Some(lock) => format!(
+                "code_hash {:#x} hash_type {:?} args 0x{}",
+                lock.code_hash,
+                lock.hash_type,
+                faster_hex::hex_string(lock.args.as_bytes()).unwrap()
+            ),
+            None => "-".to_string(),
+        };
+        println!(
+            "  block {} {:#x}: {} shannons, {}, lock {}",
+            block.number, block.hash, block.primary, block.reason, lock
+        );
     }
-
-    write_file(rendered);
+    let total: u64 = redirected.iter().map(|block| block.primary).sum();
+    println!("  total: {} shannons", total);
 }
 
 fn write_file(spec: String) {
@@ -144,19 +601,23 @@ fn write_file(spec: String) {
     println!("     ckb run");
 }
 
-fn reduce_allocate(target: u64, output: &mut Option<csv::Writer<File>>) -> Vec<IssuedCell> {
+fn allocate_records() -> Vec<LockRecord> {
     let allocate = include_bytes!("input/genesis_final.csv");
     let reader = BufReader::new(&allocate[..]);
-    let records = read_allocate(reader).unwrap();
+    read_allocate(reader).unwrap()
+}
 
-    if let Some(wtr) = output.as_mut() {
-        write_allocate_output(wtr, records.clone(), target).unwrap();
+fn reduce_allocate(target: u64, output: &mut Option<Audit>) -> Vec<IssuedCell> {
+    let records = allocate_records();
+
+    if let Some(audit) = output.as_mut() {
+        write_allocate_output(audit, "genesis_final.csv", records.clone(), target);
     }
     collect_allocate(records, target)
 }
 
 #[rustfmt::skip]
-fn load_mining_competition_records(map: &mut BTreeMap<Bytes, Capacity>, output: &mut Option<csv::Writer<File>>) {
+fn load_mining_competition_records(map: &mut BTreeMap<Bytes, Capacity>, output: &mut Option<Audit>, breakdown: &mut Option<Breakdown>) {
     let prelude = [
         ("round1.csv",         include_str!("input/round1.csv")),
         ("round2.epoch.csv",   include_str!("input/round2.epoch.csv")),
@@ -172,9 +633,11 @@ fn load_mining_competition_records(map: &mut BTreeMap<Bytes, Capacity>, output:
         let reader = BufReader::new(data.as_bytes());
         let records = read_mining_competition_record(reader).unwrap();
 
-        if let Some(wtr) = output.as_mut() {
-            wtr.write_record(&[format!("#{}", name).as_bytes(), &[], &[],&[],&[],&[]]).unwrap();
-            write_incentives_output(wtr, records.clone()).unwrap();
+        if let Some(audit) = output.as_mut() {
+            write_incentives_output(audit, name, records.clone());
+        }
+        if let Some(breakdown) = breakdown.as_mut() {
+            breakdown.add_records(name, records.clone()).unwrap();
         }
         parse_mining_competition_record(records, map).unwrap();
     }
@@ -216,7 +679,17 @@ fn foundation_reserve(target: u64) -> IssuedCell {
     }
 }
 
-fn reduce_mining_competition_records(map: BTreeMap<Bytes, Capacity>) -> Vec<IssuedCell> {
+fn reduce_mining_competition_records(
+    mut map: BTreeMap<Bytes, Capacity>,
+    output: &mut Option<Audit>,
+) -> Vec<IssuedCell> {
+    // rewards redirected to the incentives lock are issued in the last cell
+    // together with the remainder
+    let incentives_address = Address::from_str(INCENTIVES_ADDRESS).unwrap();
+    let redirected = map
+        .remove(&incentives_address.args)
+        .unwrap_or_else(Capacity::zero);
+
     let total = map
         .iter()
         .map(|(_, capacity)| *capacity)
@@ -234,15 +707,26 @@ fn reduce_mining_competition_records(map: BTreeMap<Bytes, Capacity>) -> Vec<Issu
         })
         .collect();
 
-    let remain = MINING_COMPETITION_REWARD
+    let last = MINING_COMPETITION_REWARD
         .safe_sub(total)
         .unwrap_or_else(|_| {
             exit(1);
         });
+    let remain = last.safe_sub(redirected).unwrap_or_else(|_| {
+        exit(1);
+    });
+
+    if let Some(audit) = output.as_mut() {
+        let record = LockRecord {
+            address: INCENTIVES_ADDRESS.to_string(),
+            capacity: remain.as_u64() / BYTE_SHANNONS,
+            lock: None,
+        };
+        write_computed_output(audit, "remainder", record, 0).unwrap();
+    }
 
-    let incentives_address = Address::from_str(INCENTIVES_ADDRESS).unwrap();
     issued.push(IssuedCell {
-        capacity: remain.as_u64(),
+        capacity: last.as_u64(),
         code_hash: DEFAULT_CODE_HASH.to_string(),
         args: format!(
             "0x{}",
@@ -252,3 +736,337 @@ fn reduce_mining_competition_records(map: BTreeMap<Bytes, Capacity>) -> Vec<Issu
 
     issued
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::{
+        record_fixture, MockChain, MockChainConfig, MockNode, MockTransport, ReorgSource,
+        BLOCKS_FIXTURE,
+    };
+    use ckb_types::{packed, prelude::*};
+    use std::env;
+    use std::sync::{Arc, RwLock};
+
+    const LINA_FIXTURE: &str = concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures/lina");
+    const LINA_GENESIS_HASH: &str =
+        "0x92b197aa1fba0f63633922c61c92375c9c074a93e85963554f5499fe1450d0e5";
+
+    fn generate_from(urls: &[&str], target: u64) -> (String, Consensus) {
+        let explorer = Explorer::new(urls, target, Options::default());
+        generate_spec(
+            &explorer,
+            target,
+            false,
+            None,
+            Rounding::Floor,
+            &mut None,
+            &mut None,
+        )
+    }
+
+    #[test]
+    fn test_generate_from_mock_node() {
+        let node = MockNode::start(MockChain::new(MockChainConfig::default()));
+        let target = 4;
+        let (rendered, consensus) = generate_from(&[&node.url], target);
+
+        let chain = node.chain.read().unwrap();
+        let config = &chain.config;
+        // the anchor is block 99, 99 blocks of 8 seconds after the mock genesis
+        assert!(rendered.contains("timestamp = 1573852982812\n"));
+        assert!(rendered.contains(&format!(
+            "message = \"lina {:#x}\"",
+            chain.blocks[99].hash()
+        )));
+        assert!(rendered.contains("genesis_epoch_length = 20\n"));
+        // 1.5 times the mean difficulty of epochs 1 to 4, scaled by the 99
+        // rewarded blocks over the 18M CKBytes of stage 3
+        assert!(rendered.contains("compact_target = 0x1f019709\n"));
+
+        // the three miners mined the same number of blocks and share stage 3 evenly
+        for miner in &config.miners {
+            assert!(rendered.contains(&format!(
+                "capacity = {}\nlock.code_hash = \"{}\"\nlock.args = \"0x{}\"",
+                capacity_bytes!(6_000_000).as_u64(),
+                DEFAULT_CODE_HASH,
+                faster_hex::hex_string(&miner[..]).unwrap()
+            )));
+        }
+
+        let issued = consensus.genesis_block().transactions()[0]
+            .outputs_capacity()
+            .unwrap();
+        assert_eq!(issued, INITIAL_ISSUES);
+
+        let (again, again_consensus) = generate_from(&[&node.url], target);
+        assert_eq!(rendered, again);
+        assert_eq!(
+            consensus.genesis_block().hash(),
+            again_consensus.genesis_block().hash()
+        );
+    }
+
+    #[test]
+    fn test_issued_cell_out_points() {
+        let node = MockNode::start(MockChain::new(MockChainConfig::default()));
+        let (rendered, consensus) = generate_from(&[&node.url], 4);
+        let spec: ChainSpec = toml::from_str(&rendered).unwrap();
+
+        let (tx_hash, indices) = issued_cell_out_points(&spec, &consensus).unwrap();
+        let cellbase = &consensus.genesis_block().transactions()[0];
+        assert_eq!(tx_hash, cellbase.hash());
+        assert_eq!(indices.len(), spec.genesis.issued_cells.len());
+        assert!(indices.windows(2).all(|pair| pair[0] < pair[1]));
+
+        let outputs: Vec<_> = cellbase.outputs().into_iter().collect();
+        for (cell, index) in spec.genesis.issued_cells.iter().zip(indices) {
+            let output = &outputs[index as usize];
+            let lock: packed::Script = cell.lock.clone().into();
+            assert_eq!(output.lock(), lock);
+            assert_eq!(
+                Unpack::<Capacity>::unpack(&output.capacity()),
+                cell.capacity
+            );
+        }
+    }
+
+    #[test]
+    fn test_simulate_matches_generate() {
+        let node = MockNode::start(MockChain::new(MockChainConfig::default()));
+        let scan = Explorer::new(&[&node.url], 5, Options::default())
+            .scan(4)
+            .unwrap();
+        // a saved scan evaluates the same
+        let scan: explorer::Scan =
+            serde_json::from_str(&serde_json::to_string(&scan).unwrap()).unwrap();
+
+        for target in 4..=5 {
+            let stage3 = scan.stage3(target, Rounding::Floor).unwrap();
+            let mut records = BTreeMap::new();
+            let collected = Explorer::new(&[&node.url], target, Options::default())
+                .collect(&mut records, &mut None, Rounding::Floor)
+                .unwrap();
+            assert_eq!(stage3.timestamp, collected.timestamp);
+            assert_eq!(stage3.compact_target, collected.compact_target);
+            assert_eq!(stage3.anchor_hash, collected.anchor_hash);
+            assert_eq!(stage3.epoch_length, collected.epoch_length);
+            assert_eq!(stage3.rewards.len(), records.len());
+        }
+        assert!(scan.stage3(6, Rounding::Floor).is_err());
+    }
+
+    #[test]
+    fn test_merge_redirected_rewards() {
+        let incentives_address = Address::from_str(INCENTIVES_ADDRESS).unwrap();
+        let mut records = BTreeMap::new();
+        records.insert(Bytes::from(vec![1u8; 20]), capacity_bytes!(1_000));
+        records.insert(Bytes::from(vec![2u8; 20]), capacity_bytes!(2_000));
+        records.insert(incentives_address.args.clone(), capacity_bytes!(3_000));
+
+        // the redirected rewards are merged into the last cell
+        let issued = reduce_mining_competition_records(records, &mut None);
+        assert_eq!(issued.len(), 3);
+        assert_eq!(
+            issued[2].args,
+            format!(
+                "0x{}",
+                faster_hex::hex_string(&incentives_address.args[..]).unwrap()
+            )
+        );
+        assert_eq!(
+            issued[2].capacity,
+            MINING_COMPETITION_REWARD.as_u64() - capacity_bytes!(3_000).as_u64()
+        );
+        let total: u64 = issued.iter().map(|cell| cell.capacity).sum();
+        assert_eq!(total, MINING_COMPETITION_REWARD.as_u64());
+    }
+
+    #[test]
+    fn test_import_matches_rpc() {
+        // the export source recomputes primary rewards from the issuance schedule
+        let chain = MockChain::new(MockChainConfig {
+            epoch_length: 16,
+            primary_reward: 11_986_301369863,
+            ..Default::default()
+        });
+        let path = env::temp_dir().join(format!("ckb-gbg-export-{}.json", std::process::id()));
+        // `ckb export` may leave out the genesis block
+        chain.write_export(&path, 1).unwrap();
+        let node = MockNode::start(chain);
+        let (expected, _) = generate_from(&[&node.url], 4);
+
+        let source = ExportSource::open(path.to_str().unwrap()).unwrap();
+        fs::remove_file(&path).unwrap();
+        let peer: Box<dyn ChainSource> = Box::new(RpcClient::new(&node.url));
+        let explorer = Explorer::with_sources(Box::new(source), vec![peer], 4, Options::default());
+        let (rendered, _) = generate_spec(
+            &explorer,
+            4,
+            false,
+            None,
+            Rounding::Floor,
+            &mut None,
+            &mut None,
+        );
+        assert_eq!(rendered, expected);
+    }
+
+    #[cfg(feature = "db")]
+    #[test]
+    fn test_data_dir_matches_rpc() {
+        let chain = MockChain::new(MockChainConfig::default());
+        let path = env::temp_dir().join(format!("ckb-gbg-data-dir-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&path);
+        source::write_db(&path, &chain);
+        let node = MockNode::start(chain);
+        let (expected, expected_consensus) = generate_from(&[&node.url], 4);
+
+        let source = source::DbSource::open(path.to_str().unwrap()).unwrap();
+        let explorer = Explorer::with_sources(Box::new(source), vec![], 4, Options::default());
+        let (rendered, consensus) = generate_spec(
+            &explorer,
+            4,
+            false,
+            None,
+            Rounding::Floor,
+            &mut None,
+            &mut None,
+        );
+        drop(explorer);
+        fs::remove_dir_all(&path).unwrap();
+        assert_eq!(rendered, expected);
+        assert_eq!(
+            consensus.genesis_block().hash(),
+            expected_consensus.genesis_block().hash()
+        );
+    }
+
+    fn generate_with(source: Box<dyn ChainSource>, target: u64) -> String {
+        let explorer = Explorer::with_sources(source, vec![], target, Options::default());
+        let (rendered, _) = generate_spec(
+            &explorer,
+            target,
+            false,
+            None,
+            Rounding::Floor,
+            &mut None,
+            &mut None,
+        );
+        rendered
+    }
+
+    #[test]
+    fn test_replay_recorded_rpc() {
+        let node = MockNode::start(MockChain::new(MockChainConfig::default()));
+        let (expected, _) = generate_from(&[&node.url], 4);
+
+        // the in-memory transport skips the socket but answers the same
+        let chain = MockChain::new(MockChainConfig::default());
+        assert_eq!(
+            generate_with(Box::new(MockTransport::client(chain)), 4),
+            expected
+        );
+
+        let path = env::temp_dir().join(format!("ckb-gbg-replay-{}.jsonl", std::process::id()));
+        let path = path.to_str().unwrap();
+        let mock = MockTransport {
+            chain: Arc::new(RwLock::new(MockChain::new(MockChainConfig::default()))),
+        };
+        let recorder = RecordTransport::create(mock, path).unwrap();
+        let recorded = generate_with(
+            Box::new(RpcClient::with_transport(
+                "mock://chain",
+                Box::new(recorder),
+            )),
+            4,
+        );
+        assert_eq!(recorded, expected);
+
+        let replay = ReplayTransport::open(path).unwrap();
+        fs::remove_file(path).unwrap();
+        let client = RpcClient::with_transport("mock://chain", Box::new(replay));
+        assert_eq!(generate_with(Box::new(client), 4), expected);
+
+        // a request that was never recorded fails instead of reaching a node
+        let empty =
+            env::temp_dir().join(format!("ckb-gbg-replay-empty-{}.jsonl", std::process::id()));
+        fs::write(&empty, "").unwrap();
+        let client = RpcClient::with_transport(
+            "mock://chain",
+            Box::new(ReplayTransport::open(empty.to_str().unwrap()).unwrap()),
+        );
+        fs::remove_file(&empty).unwrap();
+        assert!(client.get_tip_header().is_err());
+    }
+
+    #[test]
+    fn test_wait_restarts_after_reorg() {
+        let other = || {
+            MockChain::new(MockChainConfig {
+                miners: vec![Bytes::from(vec![7u8; 20])],
+                ..Default::default()
+            })
+        };
+        let expected = generate_with(Box::new(MockTransport::client(other())), 4);
+
+        // the first scan ends on the replaced chain, the restart scans it
+        let source = ReorgSource::new(MockChain::new(MockChainConfig::default()), other(), 110);
+        let explorer = Explorer::with_sources(Box::new(source), vec![], 4, Options::default());
+        let (rendered, _) = generate_spec(
+            &explorer,
+            4,
+            true,
+            None,
+            Rounding::Floor,
+            &mut None,
+            &mut None,
+        );
+        assert_eq!(rendered, expected);
+    }
+
+    #[test]
+    #[ignore]
+    fn record_lina_fixture() {
+        let url =
+            env::var("CKB_TESTNET_URL").unwrap_or_else(|_| "http://localhost:8114".to_string());
+        // inferred like a generation would, 11 in the testnet
+        let delay = Explorer::new(&[&url], DEFAULT_TARGET_EPOCH, Options::default())
+            .finalization_delay()
+            .unwrap();
+        record_fixture(
+            &RpcClient::new(&url),
+            DEFAULT_TARGET_EPOCH,
+            delay,
+            LINA_FIXTURE,
+        )
+        .unwrap();
+    }
+
+    // ignored until the recorded fixture is committed, see fixtures/lina/README.md
+    #[test]
+    #[ignore]
+    fn test_lina_golden() {
+        assert!(
+            Path::new(LINA_FIXTURE).join(BLOCKS_FIXTURE).exists(),
+            "{} is not recorded, see fixtures/lina/README.md",
+            LINA_FIXTURE
+        );
+        let node = MockNode::start(MockChain::from_fixture(LINA_FIXTURE));
+        let (rendered, consensus) = generate_from(&[&node.url], DEFAULT_TARGET_EPOCH);
+
+        assert!(rendered.contains("timestamp = 1573852190812\n"));
+        assert!(rendered.contains("compact_target = 0x1a08a97e\n"));
+        assert!(rendered.contains(
+            "message = \"lina 0x18e020f6b1237a3d06b75121f25a7efa0550e4b3f44f974822f471902424c104\""
+        ));
+        assert!(rendered.contains("genesis_epoch_length = 1743\n"));
+        assert_eq!(
+            format!("{:#x}", consensus.genesis_block().hash()),
+            LINA_GENESIS_HASH
+        );
+
+        let golden = fs::read_to_string(Path::new(LINA_FIXTURE).join("lina.toml")).unwrap();
+        assert_eq!(rendered, golden);
+    }
+}
@@ -2,21 +2,107 @@ use crate::address::{Address, MAINNET_PREFIX};
 use crate::input::{serialize_multisig_lock_args, LockRecord, RawRecord};
 use crate::{DEFAULT_CODE_HASH, MULTISIG_CODE_HASH};
 use bech32::{self, ToBase32};
-use ckb_types::{bytes::Bytes, H256};
+use ckb_chain_spec::ChainSpec;
+use ckb_types::{bytes::Bytes, packed::Byte32, H256};
 use failure::Error;
 use serde_derive::Serialize;
+use std::collections::{HashMap, VecDeque};
 use std::convert::{TryFrom, TryInto};
 use std::fs::File;
+use std::io::Write;
+use std::path::Path;
 use std::str::FromStr;
 
 #[derive(Debug, Serialize)]
 pub struct Output {
+    /// Input file name, `round5.stage3`, `foundation` or `remainder`.
+    pub source: String,
     pub address: String,
     pub capacity: u64,
     pub lock: Option<String>,
     pub code_hash: String,
     pub args: String,
     pub mainnet_address: String,
+    /// 1-based data row in the source CSV.
+    pub row: Option<usize>,
+    /// Out point of the issued cell this row contributes to.
+    pub tx_hash: Option<String>,
+    pub index: Option<u32>,
+}
+
+/// Rows of the `--output` file, kept until the genesis block is built so each
+/// row can be linked to the out point of its issued cell.
+#[derive(Default)]
+pub struct Audit {
+    pub outputs: Vec<Output>,
+}
+
+impl Audit {
+    pub fn push(&mut self, output: Output) {
+        self.outputs.push(output);
+    }
+
+    /// Links every row to an issued cell with the same lock.
+    ///
+    /// Rows are processed in order. When several cells share a lock, such as
+    /// duplicated allocations, each row takes the next one, and the last cell is
+    /// shared by all remaining rows, like the aggregated testnet incentives.
+    pub fn link_out_points(&mut self, spec: &ChainSpec, tx_hash: &Byte32, indices: &[u32]) {
+        let mut cells: HashMap<(String, String), VecDeque<u32>> = HashMap::new();
+        for (cell, index) in spec.genesis.issued_cells.iter().zip(indices) {
+            let key = (
+                format!("{:#x}", cell.lock.code_hash),
+                format!(
+                    "0x{}",
+                    faster_hex::hex_string(cell.lock.args.as_bytes()).unwrap()
+                ),
+            );
+            cells
+                .entry(key)
+                .or_insert_with(VecDeque::new)
+                .push_back(*index);
+        }
+
+        let tx_hash = format!("{:#x}", tx_hash);
+        for output in self.outputs.iter_mut() {
+            let key = (output.code_hash.clone(), output.args.clone());
+            if let Some(queue) = cells.get_mut(&key) {
+                let index = if queue.len() > 1 {
+                    queue.pop_front()
+                } else {
+                    queue.front().cloned()
+                };
+                output.tx_hash = Some(tx_hash.clone());
+                output.index = index;
+            }
+        }
+    }
+
+    /// Writes all rows into a single file with a header.
+    pub fn write<W: Write>(&self, wtr: &mut csv::Writer<W>) -> Result<(), Error> {
+        for output in &self.outputs {
+            wtr.serialize(output)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+
+    /// Writes one `<source>.csv` file per source into `dir`.
+    pub fn write_split(&self, dir: &Path) -> Result<(), Error> {
+        let mut writers: HashMap<&str, csv::Writer<File>> = HashMap::new();
+        for output in &self.outputs {
+            if !writers.contains_key(output.source.as_str()) {
+                let name = format!("{}.csv", output.source.trim_end_matches(".csv"));
+                writers.insert(&output.source, csv::Writer::from_path(dir.join(name))?);
+            }
+            let wtr = writers.get_mut(output.source.as_str()).expect("inserted");
+            wtr.serialize(output)?;
+        }
+        for wtr in writers.values_mut() {
+            wtr.flush()?;
+        }
+        Ok(())
+    }
 }
 
 impl TryFrom<RawRecord> for Output {
@@ -26,6 +112,7 @@ impl TryFrom<RawRecord> for Output {
         let RawRecord { address, capacity } = record;
         let decode_address = Address::from_str(&address)?;
         Ok(Output {
+            source: String::new(),
             address,
             capacity,
             lock: None,
@@ -35,6 +122,9 @@ impl TryFrom<RawRecord> for Output {
                 faster_hex::hex_string(&decode_address.args[..]).unwrap()
             ),
             mainnet_address: decode_address.mainnet_short_format()?,
+            row: None,
+            tx_hash: None,
+            index: None,
         })
     }
 }
@@ -57,17 +147,22 @@ fn convert_lock_output(record: LockRecord, target: u64) -> Result<Output, Error>
         let args = serialize_multisig_lock_args(&address, date, target)?;
         let mainnet_address = full_payload_format(args.clone())?;
         Ok(Output {
+            source: String::new(),
             address,
             capacity,
             lock,
             code_hash: MULTISIG_CODE_HASH.to_string(),
             args: format!("0x{}", faster_hex::hex_string(&args[..]).unwrap()),
             mainnet_address,
+            row: None,
+            tx_hash: None,
+            index: None,
         })
     } else {
         let decode_address = Address::from_str(&address)?;
 
         Ok(Output {
+            source: String::new(),
             address,
             capacity,
             lock,
@@ -77,33 +172,209 @@ fn convert_lock_output(record: LockRecord, target: u64) -> Result<Output, Error>
                 faster_hex::hex_string(&decode_address.args[..]).unwrap()
             ),
             mainnet_address: decode_address.mainnet_short_format()?,
+            row: None,
+            tx_hash: None,
+            index: None,
         })
     }
 }
 
 pub fn write_incentives_output<R: IntoIterator<Item = RawRecord>>(
-    wtr: &mut csv::Writer<File>,
+    audit: &mut Audit,
+    source: &str,
     records: R,
-) -> Result<(), Error> {
-    for record in records
-        .into_iter()
-        .filter_map(|record| TryInto::<Output>::try_into(record).ok())
-    {
-        wtr.serialize(record)?;
+) {
+    for (row, record) in records.into_iter().enumerate() {
+        if let Ok(mut output) = TryInto::<Output>::try_into(record) {
+            output.source = source.to_string();
+            output.row = Some(row + 1);
+            audit.push(output);
+        }
     }
-    Ok(())
 }
 
 pub fn write_allocate_output<R: IntoIterator<Item = LockRecord>>(
-    wtr: &mut csv::Writer<File>,
+    audit: &mut Audit,
+    source: &str,
     records: R,
     target: u64,
-) -> Result<(), Error> {
-    for record in records
-        .into_iter()
-        .filter_map(|record| convert_lock_output(record, target).ok())
-    {
-        wtr.serialize(record)?;
+) {
+    for (row, record) in records.into_iter().enumerate() {
+        if let Ok(mut output) = convert_lock_output(record, target) {
+            output.source = source.to_string();
+            output.row = Some(row + 1);
+            audit.push(output);
+        }
     }
+}
+
+/// Adds a row that is computed by the generator instead of read from a CSV.
+pub fn write_computed_output(
+    audit: &mut Audit,
+    source: &str,
+    record: LockRecord,
+    target: u64,
+) -> Result<(), Error> {
+    let mut output = convert_lock_output(record, target)?;
+    output.source = source.to_string();
+    audit.push(output);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::template::{IssuedCell, Spec};
+    use crate::TEMPLATE;
+    use tinytemplate::TinyTemplate;
+
+    fn args(byte: u8) -> String {
+        format!("0x{}", faster_hex::hex_string(&[byte; 20]).unwrap())
+    }
+
+    fn cell(byte: u8, capacity: u64) -> IssuedCell {
+        IssuedCell {
+            capacity,
+            code_hash: DEFAULT_CODE_HASH.to_string(),
+            args: args(byte),
+        }
+    }
+
+    /// A spec with the burned cell first, then `allocate` and `testnet_incentives`.
+    fn spec(allocate: Vec<IssuedCell>, testnet_incentives: Vec<IssuedCell>) -> ChainSpec {
+        let context = Spec {
+            timestamp: 0,
+            compact_target: "0x20ffffff".to_string(),
+            message: "0000000000000000000000000000000000000000000000000000000000000000".to_string(),
+            epoch_length: 1000,
+            allocate,
+            foundation_reserve: None,
+            testnet_incentives,
+        };
+        let mut tt = TinyTemplate::new();
+        tt.add_template("test", TEMPLATE).unwrap();
+        toml::from_str(&tt.render("test", &context).unwrap()).unwrap()
+    }
+
+    fn row(source: &str, byte: u8) -> Output {
+        Output {
+            source: source.to_string(),
+            address: String::new(),
+            capacity: 0,
+            lock: None,
+            code_hash: DEFAULT_CODE_HASH.to_string(),
+            args: args(byte),
+            mainnet_address: String::new(),
+            row: None,
+            tx_hash: None,
+            index: None,
+        }
+    }
+
+    fn link(audit: &mut Audit, spec: &ChainSpec) -> Vec<Option<u32>> {
+        let indices: Vec<u32> = (0..spec.genesis.issued_cells.len() as u32).collect();
+        audit.link_out_points(spec, &Byte32::zero(), &indices);
+        audit.outputs.iter().map(|output| output.index).collect()
+    }
+
+    #[test]
+    fn test_link_duplicated_allocations() {
+        let spec = spec(vec![cell(1, 100), cell(2, 200), cell(1, 300)], vec![]);
+        let mut audit = Audit::default();
+        audit.push(row("allocate.csv", 1));
+        audit.push(row("allocate.csv", 2));
+        audit.push(row("allocate.csv", 1));
+        assert_eq!(link(&mut audit, &spec), vec![Some(1), Some(2), Some(3)]);
+        assert!(audit
+            .outputs
+            .iter()
+            .all(|output| output.tx_hash == Some(format!("{:#x}", Byte32::zero()))));
+    }
+
+    #[test]
+    fn test_link_incentives_also_allocated() {
+        // pubkey hash 1 has an allocation and testnet incentives
+        let spec = spec(vec![cell(1, 100)], vec![cell(1, 1000), cell(2, 2000)]);
+        let mut audit = Audit::default();
+        audit.push(row("allocate.csv", 1));
+        audit.push(row("round1.csv", 1));
+        audit.push(row("round2.csv", 1));
+        audit.push(row("round1.csv", 2));
+        assert_eq!(
+            link(&mut audit, &spec),
+            vec![Some(1), Some(2), Some(2), Some(3)]
+        );
+    }
+
+    #[test]
+    fn test_link_remainder_row() {
+        // the remainder is aggregated into the incentives cell of its lock
+        let spec = spec(vec![], vec![cell(2, 2000), cell(3, 3000)]);
+        let mut audit = Audit::default();
+        audit.push(row("round1.csv", 2));
+        audit.push(row("round1.csv", 3));
+        audit.push(row("remainder", 3));
+        audit.push(row("unknown", 4));
+        assert_eq!(
+            link(&mut audit, &spec),
+            vec![Some(1), Some(2), Some(2), None]
+        );
+        assert_eq!(audit.outputs[3].tx_hash, None);
+    }
+
+    #[test]
+    fn test_write_source_column() {
+        let mut audit = Audit::default();
+        audit.push(row("allocate.csv", 1));
+        audit.push(row("remainder", 2));
+        let mut wtr = csv::Writer::from_writer(vec![]);
+        audit.write(&mut wtr).unwrap();
+        let written = String::from_utf8(wtr.into_inner().unwrap()).unwrap();
+        let mut lines = written.lines();
+        assert_eq!(
+            lines.next(),
+            Some("source,address,capacity,lock,code_hash,args,mainnet_address,row,tx_hash,index")
+        );
+        let sources: Vec<&str> = lines.map(|line| line.split(',').next().unwrap()).collect();
+        assert_eq!(sources, vec!["allocate.csv", "remainder"]);
+    }
+
+    #[test]
+    fn test_write_split() {
+        let dir = std::env::temp_dir().join(format!("ckb-gbg-split-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut audit = Audit::default();
+        audit.push(row("allocate.csv", 1));
+        audit.push(row("round1.csv", 2));
+        audit.push(row("allocate.csv", 3));
+        audit.push(row("remainder", 4));
+        audit.write_split(&dir).unwrap();
+
+        let mut names: Vec<String> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        assert_eq!(names, vec!["allocate.csv", "remainder.csv", "round1.csv"]);
+
+        for (name, expected) in &[
+            ("allocate.csv", vec![args(1), args(3)]),
+            ("round1.csv", vec![args(2)]),
+            ("remainder.csv", vec![args(4)]),
+        ] {
+            let source = name.trim_end_matches(".csv");
+            let mut rdr = csv::Reader::from_path(dir.join(name)).unwrap();
+            let headers = rdr.headers().unwrap().clone();
+            assert_eq!(&headers[0], "source");
+            assert_eq!(&headers[5], "args");
+            let records: Vec<csv::StringRecord> = rdr.records().map(Result::unwrap).collect();
+            assert!(records
+                .iter()
+                .all(|record| record[0].trim_end_matches(".csv") == source));
+            let written: Vec<String> = records.iter().map(|record| record[5].to_string()).collect();
+            assert_eq!(&written, expected);
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
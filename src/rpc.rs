@@ -2,18 +2,14 @@ mod id_generator;
 #[macro_use]
 mod macros;
 mod error;
+#[cfg(test)]
+pub mod mock;
+mod transport;
+
+pub use transport::{HttpOptions, HttpTransport, RecordTransport, ReplayTransport, Transport};
 
 use ckb_jsonrpc_types::{BlockNumber, BlockReward, BlockView, EpochNumber, EpochView, HeaderView};
 use ckb_types::H256;
-use lazy_static::lazy_static;
-
-lazy_static! {
-    pub static ref HTTP_CLIENT: reqwest::Client = reqwest::Client::builder()
-        .gzip(true)
-        .timeout(::std::time::Duration::from_secs(30))
-        .build()
-        .expect("reqwest Client build");
-}
 
 jsonrpc!(pub struct RpcClient {
     pub fn get_block_by_number(&self, _number: BlockNumber) -> Option<BlockView>;
@@ -10,16 +10,27 @@ macro_rules! jsonrpc {
     ) => (
         $(#[$struct_attr])*
         pub struct $struct_name {
-            pub client: &'static reqwest::Client,
-            pub url: reqwest::Url,
+            pub transport: Box<dyn $crate::rpc::Transport>,
+            pub url: String,
             pub id_generator: $crate::rpc::id_generator::IdGenerator,
         }
 
         impl $struct_name {
             pub fn new(url: &str) -> Self {
-                let url = reqwest::Url::parse(url).expect("ckb url, e.g. \"http://127.0.0.1:8114\"");
+                $struct_name::with_http(url, &Default::default()).expect("ckb url, e.g. \"http://127.0.0.1:8114\"")
+            }
+
+            /// Connects over HTTP with `options`, the credentials in `url` are left out of its name.
+            pub fn with_http(url: &str, options: &$crate::rpc::HttpOptions) -> Result<Self, failure::Error> {
+                let transport = $crate::rpc::HttpTransport::new(reqwest::Url::parse(url)?, options)?;
+                let name = transport.url().to_string();
+                Ok($struct_name::with_transport(&name, Box::new(transport)))
+            }
+
+            /// Sends the requests through `transport`, `url` only names the endpoint.
+            pub fn with_transport(url: &str, transport: Box<dyn $crate::rpc::Transport>) -> Self {
                 let id_generator = $crate::rpc::id_generator::IdGenerator::new();
-                $struct_name { url, id_generator, client: &$crate::rpc::HTTP_CLIENT, }
+                $struct_name { transport, url: url.to_string(), id_generator }
             }
 
             $(
@@ -35,8 +46,7 @@ macro_rules! jsonrpc {
                     req_json.insert("method".to_owned(), serde_json::json!(method));
                     req_json.insert("params".to_owned(), params);
 
-                    let mut resp = $selff.client.post($selff.url.clone()).json(&req_json).send()?;
-                    let output = resp.json::<ckb_jsonrpc_types::response::Output>()?;
+                    let output = $selff.transport.send(&serde_json::Value::Object(req_json))?;
                     match output {
                         ckb_jsonrpc_types::response::Output::Success(success) => {
                             serde_json::from_value(success.result).map_err(Into::into)
@@ -0,0 +1,463 @@
+//! An in-process CKB node serving a synthetic chain over HTTP JSON-RPC, or
+//! directly through `MockTransport`.
+
+use crate::rpc::{RpcClient, Transport};
+use crate::source::{CellbaseView, ChainSource};
+use crate::DEFAULT_CODE_HASH;
+use ckb_jsonrpc_types::{
+    response::Output, BlockReward, BlockView as JsonBlockView, EpochView,
+    HeaderView as JsonHeaderView, Uint64,
+};
+use ckb_types::{
+    bytes::Bytes,
+    core::{
+        BlockBuilder, BlockView, Capacity, EpochNumberWithFraction, ScriptHashType,
+        TransactionBuilder,
+    },
+    packed::{Byte32, CellInput, CellOutput, CellbaseWitness, Script},
+    prelude::*,
+    H256,
+};
+use failure::{format_err, Error};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+
+pub const GENESIS_TIMESTAMP: u64 = 1_573_852_190_812;
+pub const BLOCK_INTERVAL: u64 = 8_000;
+
+#[derive(Clone)]
+pub struct MockChainConfig {
+    pub epoch_length: u64,
+    pub epochs: u64,
+    /// Compact target of each epoch, the last one is reused for later epochs.
+    pub compact_targets: Vec<u32>,
+    /// Blake160 pubkey hashes of the miners, block `n` is mined by `miners[n % len]`.
+    pub miners: Vec<Bytes>,
+    pub primary_reward: u64,
+    /// Blocks whose cellbase witness is not a `CellbaseWitness`.
+    pub malformed_witnesses: Vec<u64>,
+    /// Cellbase of block `n` pays block `n - finalization_delay`.
+    pub finalization_delay: u64,
+}
+
+impl Default for MockChainConfig {
+    fn default() -> Self {
+        MockChainConfig {
+            epoch_length: 20,
+            epochs: 7,
+            compact_targets: vec![0x1e08_3126, 0x1e07_8000, 0x1e06_a000, 0x1e05_f000],
+            miners: vec![
+                Bytes::from(vec![1u8; 20]),
+                Bytes::from(vec![2u8; 20]),
+                Bytes::from(vec![3u8; 20]),
+            ],
+            primary_reward: 1_917_80821917,
+            malformed_witnesses: vec![],
+            finalization_delay: 11,
+        }
+    }
+}
+
+pub struct MockChain {
+    pub config: MockChainConfig,
+    pub blocks: Vec<BlockView>,
+    pub epochs: Vec<EpochView>,
+    pub rewards: HashMap<Byte32, BlockReward>,
+}
+
+impl MockChain {
+    pub fn new(config: MockChainConfig) -> MockChain {
+        let count = config.epoch_length * config.epochs;
+        let mut blocks: Vec<BlockView> = Vec::with_capacity(count as usize);
+        let mut rewards = HashMap::with_capacity(count as usize);
+        for number in 0..count {
+            let parent_hash = blocks
+                .last()
+                .map(|parent| parent.hash())
+                .unwrap_or_else(Byte32::zero);
+            let block = config.build_block(number, parent_hash);
+            rewards.insert(block.hash(), block_reward(config.primary_reward));
+            blocks.push(block);
+        }
+        let epochs = (0..config.epochs)
+            .map(|number| EpochView {
+                number: number.into(),
+                start_number: (number * config.epoch_length).into(),
+                length: config.epoch_length.into(),
+                compact_target: config.compact_target(number).into(),
+            })
+            .collect();
+        MockChain {
+            config,
+            blocks,
+            epochs,
+            rewards,
+        }
+    }
+
+    /// Loads a chain recorded by `record_fixture`.
+    pub fn from_fixture<P: AsRef<Path>>(dir: P) -> MockChain {
+        let dir = dir.as_ref();
+        let blocks = read_lines::<JsonBlockView>(&dir.join(BLOCKS_FIXTURE))
+            .into_iter()
+            .map(Into::into)
+            .collect();
+        let epochs = read_lines::<EpochView>(&dir.join(EPOCHS_FIXTURE));
+        let rewards = read_lines::<(H256, BlockReward)>(&dir.join(REWARDS_FIXTURE))
+            .into_iter()
+            .map(|(hash, reward)| (hash.pack(), reward))
+            .collect();
+        MockChain {
+            config: MockChainConfig::default(),
+            blocks,
+            epochs,
+            rewards,
+        }
+    }
+
+    /// Writes the blocks from `first` one JSON block per line, like `ckb export`.
+    pub fn write_export<P: AsRef<Path>>(&self, path: P, first: u64) -> Result<(), Error> {
+        let mut file = File::create(path)?;
+        for block in &self.blocks[first as usize..] {
+            let block = JsonBlockView::from(block.clone());
+            writeln!(file, "{}", serde_json::to_string(&block)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn tip(&self) -> &BlockView {
+        self.blocks.last().expect("non-empty chain")
+    }
+
+    fn handle(&self, method: &str, params: Value) -> Value {
+        let number = || {
+            serde_json::from_value::<(Uint64,)>(params.clone())
+                .map(|(number,)| Into::<u64>::into(number) as usize)
+                .expect("number param")
+        };
+        match method {
+            "get_tip_header" => json!(JsonHeaderView::from(self.tip().header())),
+            "get_block_by_number" => json!(self
+                .blocks
+                .get(number())
+                .map(|block| JsonBlockView::from(block.clone()))),
+            "get_header_by_number" => json!(self
+                .blocks
+                .get(number())
+                .map(|block| JsonHeaderView::from(block.header()))),
+            "get_block_hash" => json!(self
+                .blocks
+                .get(number())
+                .map(|block| Unpack::<H256>::unpack(&block.hash()))),
+            "get_epoch_by_number" => json!(self.epochs.get(number())),
+            "get_cellbase_output_capacity_details" => {
+                let (hash,) = serde_json::from_value::<(H256,)>(params).expect("hash param");
+                json!(self.rewards.get(&hash.pack()))
+            }
+            _ => panic!("unexpected method {}", method),
+        }
+    }
+}
+
+fn block_reward(primary: u64) -> BlockReward {
+    BlockReward {
+        total: primary.into(),
+        primary: primary.into(),
+        secondary: 0u64.into(),
+        tx_fee: 0u64.into(),
+        proposal_reward: 0u64.into(),
+    }
+}
+
+pub const BLOCKS_FIXTURE: &str = "blocks.jsonl";
+pub const EPOCHS_FIXTURE: &str = "epochs.jsonl";
+pub const REWARDS_FIXTURE: &str = "rewards.jsonl";
+
+fn read_lines<T: DeserializeOwned>(path: &Path) -> Vec<T> {
+    let file = File::open(path).unwrap_or_else(|e| panic!("open {}: {}", path.display(), e));
+    BufReader::new(file)
+        .lines()
+        .map(|line| serde_json::from_str(&line.expect("read fixture")).expect("parse fixture"))
+        .collect()
+}
+
+/// Records every response `Explorer::collect` needs for `target` from a live
+/// node with `finalization_delay` into `dir`, in the format read by
+/// `MockChain::from_fixture`.
+pub fn record_fixture<P: AsRef<Path>>(
+    rpc: &RpcClient,
+    target: u64,
+    finalization_delay: u64,
+    dir: P,
+) -> Result<(), Error> {
+    let dir = dir.as_ref();
+    fs::create_dir_all(dir)?;
+    let missing = |what: String| format_err!("{} not found", what);
+
+    let mut epochs = File::create(dir.join(EPOCHS_FIXTURE))?;
+    for number in 0..=(target + 1) {
+        let epoch = rpc
+            .get_epoch_by_number(number.into())?
+            .ok_or_else(|| missing(format!("epoch {}", number)))?;
+        writeln!(epochs, "{}", serde_json::to_string(&epoch)?)?;
+    }
+
+    // the scan stops `finalization_delay` blocks into epoch target+1
+    let next_epoch = rpc
+        .get_epoch_by_number((target + 1).into())?
+        .ok_or_else(|| missing(format!("epoch {}", target + 1)))?;
+    let last = Into::<u64>::into(next_epoch.start_number) + finalization_delay;
+
+    let mut blocks = File::create(dir.join(BLOCKS_FIXTURE))?;
+    let mut rewards = File::create(dir.join(REWARDS_FIXTURE))?;
+    for number in 0..=last {
+        let block = rpc
+            .get_block_by_number(number.into())?
+            .ok_or_else(|| missing(format!("block {}", number)))?;
+        let hash = block.header.hash.clone();
+        writeln!(blocks, "{}", serde_json::to_string(&block)?)?;
+        if let Some(reward) = rpc.get_cellbase_output_capacity_details(hash.clone())? {
+            writeln!(rewards, "{}", serde_json::to_string(&(hash, reward))?)?;
+        }
+    }
+    Ok(())
+}
+
+impl MockChainConfig {
+    pub fn compact_target(&self, epoch: u64) -> u32 {
+        let index = (epoch as usize).min(self.compact_targets.len() - 1);
+        self.compact_targets[index]
+    }
+
+    pub fn miner_lock(&self, number: u64) -> Script {
+        let args = &self.miners[(number as usize) % self.miners.len()];
+        Script::new_builder()
+            .code_hash(H256::from_str(&DEFAULT_CODE_HASH[2..]).unwrap().pack())
+            .hash_type(ScriptHashType::Type.into())
+            .args(args.pack())
+            .build()
+    }
+
+    pub fn build_block(&self, number: u64, parent_hash: Byte32) -> BlockView {
+        let epoch = number / self.epoch_length;
+        let witness = if self.malformed_witnesses.contains(&number) {
+            Bytes::from(vec![0xff; 3])
+        } else {
+            CellbaseWitness::new_builder()
+                .lock(self.miner_lock(number))
+                .build()
+                .as_bytes()
+        };
+        let mut cellbase = TransactionBuilder::default()
+            .input(CellInput::new_cellbase_input(number))
+            .witness(witness.pack());
+        if number > self.finalization_delay {
+            let output = CellOutput::new_builder()
+                .capacity(Capacity::shannons(self.primary_reward).pack())
+                .lock(self.miner_lock(number - self.finalization_delay))
+                .build();
+            cellbase = cellbase.output(output).output_data(Bytes::new().pack());
+        }
+        let cellbase = cellbase.build();
+        BlockBuilder::default()
+            .number(number.pack())
+            .epoch(
+                EpochNumberWithFraction::new(epoch, number % self.epoch_length, self.epoch_length)
+                    .full_value()
+                    .pack(),
+            )
+            .timestamp((GENESIS_TIMESTAMP + number * BLOCK_INTERVAL).pack())
+            .compact_target(self.compact_target(epoch).pack())
+            .parent_hash(parent_hash)
+            .transaction(cellbase)
+            .build()
+    }
+}
+
+/// Serves `chain` on a random local port until the process exits.
+pub struct MockNode {
+    pub url: String,
+    pub chain: Arc<RwLock<MockChain>>,
+    /// Headers of the last request, names in lower case.
+    pub headers: Arc<Mutex<Vec<(String, String)>>>,
+}
+
+impl MockNode {
+    pub fn start(chain: MockChain) -> MockNode {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind mock node");
+        let url = format!("http://{}", listener.local_addr().expect("local addr"));
+        let chain = Arc::new(RwLock::new(chain));
+        let headers = Arc::new(Mutex::new(Vec::new()));
+        let shared = Arc::clone(&chain);
+        let shared_headers = Arc::clone(&headers);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let chain = Arc::clone(&shared);
+                let headers = Arc::clone(&shared_headers);
+                if let Ok(stream) = stream {
+                    thread::spawn(move || serve(stream, &chain, &headers));
+                }
+            }
+        });
+        MockNode {
+            url,
+            chain,
+            headers,
+        }
+    }
+}
+
+/// Serves a `MockChain` to an `RpcClient` in process, without a socket.
+pub struct MockTransport {
+    pub chain: Arc<RwLock<MockChain>>,
+}
+
+impl MockTransport {
+    pub fn client(chain: MockChain) -> RpcClient {
+        let transport = MockTransport {
+            chain: Arc::new(RwLock::new(chain)),
+        };
+        RpcClient::with_transport("mock://chain", Box::new(transport))
+    }
+}
+
+impl Transport for MockTransport {
+    fn send(&self, request: &Value) -> Result<Output, Error> {
+        let response = respond(&self.chain.read().expect("read chain"), request);
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+/// Serves `chain` until the scan fetches block `trigger`, then replaces it by
+/// `next`, like a reorg between the scan and its verification.
+pub struct ReorgSource {
+    client: RpcClient,
+    chain: Arc<RwLock<MockChain>>,
+    next: RefCell<Option<MockChain>>,
+    trigger: u64,
+}
+
+impl ReorgSource {
+    pub fn new(chain: MockChain, next: MockChain, trigger: u64) -> ReorgSource {
+        let chain = Arc::new(RwLock::new(chain));
+        let transport = MockTransport {
+            chain: Arc::clone(&chain),
+        };
+        ReorgSource {
+            client: RpcClient::with_transport("mock://chain", Box::new(transport)),
+            chain,
+            next: RefCell::new(Some(next)),
+            trigger,
+        }
+    }
+}
+
+impl ChainSource for ReorgSource {
+    fn name(&self) -> String {
+        self.client.url.clone()
+    }
+
+    fn get_block_by_number(&self, number: Uint64) -> Result<Option<JsonBlockView>, Error> {
+        self.client.get_block_by_number(number)
+    }
+
+    fn get_header_by_number(&self, number: Uint64) -> Result<Option<JsonHeaderView>, Error> {
+        self.client.get_header_by_number(number)
+    }
+
+    fn get_cellbase_by_number(&self, number: Uint64) -> Result<Option<CellbaseView>, Error> {
+        let reorg = number == Uint64::from(self.trigger);
+        let view = ChainSource::get_cellbase_by_number(&self.client, number)?;
+        if reorg {
+            if let Some(next) = self.next.borrow_mut().take() {
+                *self.chain.write().expect("write chain") = next;
+            }
+        }
+        Ok(view)
+    }
+
+    fn get_block_hash(&self, number: Uint64) -> Result<Option<H256>, Error> {
+        self.client.get_block_hash(number)
+    }
+
+    fn get_cellbase_output_capacity_details(
+        &self,
+        hash: H256,
+    ) -> Result<Option<BlockReward>, Error> {
+        self.client.get_cellbase_output_capacity_details(hash)
+    }
+
+    fn get_tip_header(&self) -> Result<JsonHeaderView, Error> {
+        self.client.get_tip_header()
+    }
+
+    fn get_epoch_by_number(&self, number: Uint64) -> Result<Option<EpochView>, Error> {
+        self.client.get_epoch_by_number(number)
+    }
+}
+
+fn respond(chain: &MockChain, request: &Value) -> Value {
+    let method = request["method"].as_str().expect("method");
+    json!({
+        "jsonrpc": "2.0",
+        "result": chain.handle(method, request["params"].clone()),
+        "id": request["id"],
+    })
+}
+
+fn serve(stream: TcpStream, chain: &RwLock<MockChain>, seen: &Mutex<Vec<(String, String)>>) {
+    let mut writer = stream.try_clone().expect("clone stream");
+    let mut reader = BufReader::new(stream);
+    loop {
+        let mut content_length = 0;
+        let mut headers = Vec::new();
+        let mut line = String::new();
+        // request line
+        if reader.read_line(&mut line).unwrap_or(0) == 0 {
+            return;
+        }
+        loop {
+            line.clear();
+            if reader.read_line(&mut line).unwrap_or(0) == 0 {
+                return;
+            }
+            let header = line.trim_end();
+            if header.is_empty() {
+                break;
+            }
+            let mut parts = header.splitn(2, ':');
+            let name = parts.next().unwrap_or("").trim().to_ascii_lowercase();
+            let value = parts.next().unwrap_or("").trim().to_string();
+            if name == "content-length" {
+                content_length = value.parse().unwrap_or(0);
+            }
+            headers.push((name, value));
+        }
+        *seen.lock().expect("lock headers") = headers;
+
+        let mut body = vec![0u8; content_length];
+        if reader.read_exact(&mut body).is_err() {
+            return;
+        }
+        let request: Value = serde_json::from_slice(&body).expect("json request");
+        let response = respond(&chain.read().expect("read chain"), &request).to_string();
+        let written = write!(
+            writer,
+            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+            response.len(),
+            response
+        );
+        if written.is_err() || writer.flush().is_err() {
+            return;
+        }
+    }
+}
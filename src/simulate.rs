@@ -0,0 +1,67 @@
+use crate::address::Address;
+use crate::explorer::{Scan, Stage3};
+use crate::BYTE_SHANNONS;
+use chrono::{offset::TimeZone, Utc};
+use ckb_types::packed::Script;
+use ckb_types::prelude::*;
+use failure::Error;
+use std::fs;
+
+pub fn save_scan(path: &str, scan: &Scan) -> Result<(), Error> {
+    fs::write(path, serde_json::to_string(scan)?)?;
+    Ok(())
+}
+
+pub fn load_scan(path: &str) -> Result<Scan, Error> {
+    Ok(serde_json::from_str(&fs::read_to_string(path)?)?)
+}
+
+pub fn print_table(results: &[Stage3]) {
+    println!(
+        "{:>6} {:>10} {:>20} {:>7} {:>10} {:>18} {:>10} {:>12} {:>6}",
+        "target",
+        "anchor",
+        "timestamp (UTC)",
+        "miners",
+        "redirected",
+        "stage3 total (CKB)",
+        "loss (CKB)",
+        "compact",
+        "length"
+    );
+    for stage3 in results {
+        println!(
+            "{:>6} {:>10} {:>20} {:>7} {:>10} {:>18} {:>10} {:>#12x} {:>6}",
+            stage3.target,
+            stage3.anchor_number,
+            Utc.timestamp_millis(stage3.timestamp as i64)
+                .format("%Y-%m-%d %H:%M:%S"),
+            stage3.rewards.len(),
+            stage3.redirected.len(),
+            stage3.total.as_u64() / BYTE_SHANNONS,
+            stage3.rounding_loss.as_u64() / BYTE_SHANNONS,
+            stage3.compact_target,
+            stage3.epoch_length
+        );
+    }
+}
+
+/// Writes the stage 3 reward of every miner for every target as CSV.
+pub fn write_rewards(path: &str, results: &[Stage3]) -> Result<(), Error> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    wtr.write_record(&["target", "mainnet_address", "args", "capacity"])?;
+    for stage3 in results {
+        for (lock, reward_ckb) in &stage3.rewards {
+            let lock: Script = lock.clone().into();
+            let args = lock.args().raw_data();
+            wtr.write_record(&[
+                stage3.target.to_string(),
+                Address::new(args.clone()).mainnet_short_format()?,
+                format!("0x{}", faster_hex::hex_string(&args[..]).unwrap()),
+                reward_ckb.to_string(),
+            ])?;
+        }
+    }
+    wtr.flush()?;
+    Ok(())
+}
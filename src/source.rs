@@ -0,0 +1,105 @@
+//! Where the explorer reads the chain from.
+
+mod cache;
+#[cfg(feature = "db")]
+mod db;
+mod export;
+
+pub use cache::{CachedSource, DEFAULT_SAFE_DEPTH};
+#[cfg(all(test, feature = "db"))]
+pub use db::write_db;
+#[cfg(feature = "db")]
+pub use db::DbSource;
+pub use export::ExportSource;
+
+use crate::rpc::RpcClient;
+use ckb_jsonrpc_types::{
+    BlockNumber, BlockReward, BlockView, EpochNumber, EpochView, HeaderView, TransactionView,
+};
+use ckb_types::{core, packed::Byte32, prelude::*, H256};
+use failure::Error;
+use serde_derive::{Deserialize, Serialize};
+
+/// The header and cellbase of a block, with the hashes of the other
+/// transactions so the cellbase can still be checked against the
+/// transactions root. This is all the scan reads from a block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CellbaseView {
+    pub header: HeaderView,
+    /// `None` for a malformed block without transactions.
+    pub cellbase: Option<TransactionView>,
+    /// Hashes of the transactions after the cellbase.
+    pub tx_hashes: Vec<H256>,
+    /// Witness hashes of the transactions after the cellbase.
+    pub witness_hashes: Vec<H256>,
+}
+
+impl From<BlockView> for CellbaseView {
+    fn from(json: BlockView) -> Self {
+        let header = json.header.clone();
+        let block: core::BlockView = json.into();
+        let rest = |hashes: &[Byte32]| -> Vec<H256> {
+            hashes.iter().skip(1).map(|hash| hash.unpack()).collect()
+        };
+        CellbaseView {
+            header,
+            cellbase: block.transactions().first().cloned().map(Into::into),
+            tx_hashes: rest(block.tx_hashes()),
+            witness_hashes: rest(block.tx_witness_hashes()),
+        }
+    }
+}
+
+/// The subset of the CKB JSON-RPC the explorer uses, so a chain can also be read
+/// from other sources than a running node.
+pub trait ChainSource {
+    /// Names the source in error messages, such as the rpc url.
+    fn name(&self) -> String;
+    fn get_block_by_number(&self, number: BlockNumber) -> Result<Option<BlockView>, Error>;
+    fn get_header_by_number(&self, number: BlockNumber) -> Result<Option<HeaderView>, Error>;
+    fn get_block_hash(&self, number: BlockNumber) -> Result<Option<H256>, Error>;
+    /// The header and cellbase of block `number`. Taken from the whole block by
+    /// default, the node has no cheaper call.
+    fn get_cellbase_by_number(&self, number: BlockNumber) -> Result<Option<CellbaseView>, Error> {
+        Ok(self.get_block_by_number(number)?.map(Into::into))
+    }
+    fn get_cellbase_output_capacity_details(
+        &self,
+        hash: H256,
+    ) -> Result<Option<BlockReward>, Error>;
+    fn get_tip_header(&self) -> Result<HeaderView, Error>;
+    fn get_epoch_by_number(&self, number: EpochNumber) -> Result<Option<EpochView>, Error>;
+}
+
+impl ChainSource for RpcClient {
+    fn name(&self) -> String {
+        self.url.clone()
+    }
+
+    fn get_block_by_number(&self, number: BlockNumber) -> Result<Option<BlockView>, Error> {
+        RpcClient::get_block_by_number(self, number)
+    }
+
+    fn get_header_by_number(&self, number: BlockNumber) -> Result<Option<HeaderView>, Error> {
+        RpcClient::get_header_by_number(self, number)
+    }
+
+    fn get_block_hash(&self, number: BlockNumber) -> Result<Option<H256>, Error> {
+        RpcClient::get_block_hash(self, number)
+    }
+
+    fn get_cellbase_output_capacity_details(
+        &self,
+        hash: H256,
+    ) -> Result<Option<BlockReward>, Error> {
+        RpcClient::get_cellbase_output_capacity_details(self, hash)
+    }
+
+    fn get_tip_header(&self) -> Result<HeaderView, Error> {
+        RpcClient::get_tip_header(self)
+    }
+
+    fn get_epoch_by_number(&self, number: EpochNumber) -> Result<Option<EpochView>, Error> {
+        RpcClient::get_epoch_by_number(self, number)
+    }
+}
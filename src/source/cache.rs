@@ -0,0 +1,313 @@
+use super::{CellbaseView, ChainSource};
+use ckb_jsonrpc_types::{BlockNumber, BlockReward, BlockView, EpochNumber, EpochView, HeaderView};
+use ckb_types::H256;
+use failure::Error;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_derive::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Blocks at least this deep below the tip are cached by default.
+pub const DEFAULT_SAFE_DEPTH: u64 = 1_000;
+
+/// Keeps the responses of another source that cannot change in a directory,
+/// one file per method and params, so repeated runs do not fetch the same
+/// historical blocks again.
+///
+/// Only blocks `safe_depth` below the tip are cached. A cached block, header
+/// or epoch is dropped when the block hash it was read at no longer matches
+/// the chain, `get_block_hash` is always asked from the source for that.
+pub struct CachedSource {
+    inner: Box<dyn ChainSource>,
+    dir: PathBuf,
+    safe_number: u64,
+    hashes: RefCell<HashMap<u64, Option<H256>>>,
+}
+
+/// How long a response stays valid in the cache.
+enum Validity {
+    Uncached,
+    Forever,
+    /// As long as the block with the number has the hash.
+    WhileBlock(u64, H256),
+}
+
+#[derive(Serialize, Deserialize)]
+struct Entry {
+    anchor: Option<(u64, H256)>,
+    value: Value,
+}
+
+impl CachedSource {
+    pub fn open(
+        inner: Box<dyn ChainSource>,
+        dir: &str,
+        safe_depth: u64,
+    ) -> Result<CachedSource, Error> {
+        fs::create_dir_all(dir)?;
+        let tip: u64 = inner.get_tip_header()?.inner.number.into();
+        Ok(CachedSource {
+            inner,
+            dir: PathBuf::from(dir),
+            safe_number: tip.saturating_sub(safe_depth),
+            hashes: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// The hash of block `number` in the source, remembered for this run once
+    /// the block is deep enough.
+    fn live_hash(&self, number: u64) -> Result<Option<H256>, Error> {
+        if let Some(hash) = self.hashes.borrow().get(&number) {
+            return Ok(hash.clone());
+        }
+        let hash = self.inner.get_block_hash(number.into())?;
+        if number <= self.safe_number {
+            self.hashes.borrow_mut().insert(number, hash.clone());
+        }
+        Ok(hash)
+    }
+
+    fn path(&self, method: &str, params: &Value) -> PathBuf {
+        let mut hasher = Sha256::new();
+        hasher.input(format!("{} {}", method, params).as_bytes());
+        self.dir.join(format!("{:x}.json", hasher.result()))
+    }
+
+    fn read<T: DeserializeOwned>(&self, path: &Path) -> Result<Option<T>, Error> {
+        // a missing or unreadable entry is fetched again
+        let entry: Entry = match fs::read(path)
+            .ok()
+            .and_then(|content| serde_json::from_slice(&content).ok())
+        {
+            Some(entry) => entry,
+            None => return Ok(None),
+        };
+        if let Some((number, hash)) = &entry.anchor {
+            if self.live_hash(*number)?.as_ref() != Some(hash) {
+                let _ = fs::remove_file(path);
+                return Ok(None);
+            }
+        }
+        Ok(serde_json::from_value(entry.value).ok())
+    }
+
+    fn write<T: Serialize>(
+        &self,
+        path: &Path,
+        anchor: Option<(u64, H256)>,
+        value: &T,
+    ) -> Result<(), Error> {
+        let entry = Entry {
+            anchor,
+            value: serde_json::to_value(value)?,
+        };
+        // rename so an interrupted run never leaves a truncated entry
+        let partial = path.with_extension("partial");
+        fs::write(&partial, serde_json::to_vec(&entry)?)?;
+        fs::rename(&partial, path)?;
+        Ok(())
+    }
+
+    fn cached<T, F, V>(
+        &self,
+        method: &str,
+        params: Value,
+        fetch: F,
+        validity: V,
+    ) -> Result<T, Error>
+    where
+        T: Serialize + DeserializeOwned,
+        F: FnOnce() -> Result<T, Error>,
+        V: FnOnce(&T) -> Result<Validity, Error>,
+    {
+        let path = self.path(method, &params);
+        if let Some(value) = self.read(&path)? {
+            return Ok(value);
+        }
+        let value = fetch()?;
+        match validity(&value)? {
+            Validity::Uncached => {}
+            Validity::Forever => self.write(&path, None, &value)?,
+            Validity::WhileBlock(number, hash) => {
+                self.write(&path, Some((number, hash)), &value)?
+            }
+        }
+        Ok(value)
+    }
+
+    fn block_validity(&self, number: u64, hash: Option<&H256>) -> Validity {
+        match hash {
+            Some(hash) if number <= self.safe_number => Validity::WhileBlock(number, hash.clone()),
+            _ => Validity::Uncached,
+        }
+    }
+}
+
+impl ChainSource for CachedSource {
+    fn name(&self) -> String {
+        self.inner.name()
+    }
+
+    fn get_block_by_number(&self, number: BlockNumber) -> Result<Option<BlockView>, Error> {
+        let number: u64 = number.into();
+        self.cached(
+            "get_block_by_number",
+            json!([number]),
+            || self.inner.get_block_by_number(number.into()),
+            |block| {
+                let hash = block.as_ref().map(|block| &block.header.hash);
+                Ok(self.block_validity(number, hash))
+            },
+        )
+    }
+
+    fn get_header_by_number(&self, number: BlockNumber) -> Result<Option<HeaderView>, Error> {
+        let number: u64 = number.into();
+        self.cached(
+            "get_header_by_number",
+            json!([number]),
+            || self.inner.get_header_by_number(number.into()),
+            |header| Ok(self.block_validity(number, header.as_ref().map(|header| &header.hash))),
+        )
+    }
+
+    fn get_cellbase_by_number(&self, number: BlockNumber) -> Result<Option<CellbaseView>, Error> {
+        let number: u64 = number.into();
+        self.cached(
+            "get_cellbase_by_number",
+            json!([number]),
+            || self.inner.get_cellbase_by_number(number.into()),
+            |view| Ok(self.block_validity(number, view.as_ref().map(|view| &view.header.hash))),
+        )
+    }
+
+    fn get_block_hash(&self, number: BlockNumber) -> Result<Option<H256>, Error> {
+        self.live_hash(number.into())
+    }
+
+    fn get_cellbase_output_capacity_details(
+        &self,
+        hash: H256,
+    ) -> Result<Option<BlockReward>, Error> {
+        // keyed by the block hash, the details never change
+        self.cached(
+            "get_cellbase_output_capacity_details",
+            json!([hash]),
+            || {
+                self.inner
+                    .get_cellbase_output_capacity_details(hash.clone())
+            },
+            |reward| {
+                Ok(if reward.is_some() {
+                    Validity::Forever
+                } else {
+                    Validity::Uncached
+                })
+            },
+        )
+    }
+
+    fn get_tip_header(&self) -> Result<HeaderView, Error> {
+        self.inner.get_tip_header()
+    }
+
+    fn get_epoch_by_number(&self, number: EpochNumber) -> Result<Option<EpochView>, Error> {
+        // the start block header fixes the length and target of the epoch
+        let number: u64 = number.into();
+        self.cached(
+            "get_epoch_by_number",
+            json!([number]),
+            || self.inner.get_epoch_by_number(number.into()),
+            |epoch| {
+                let epoch = match epoch {
+                    Some(epoch) => epoch.clone(),
+                    None => return Ok(Validity::Uncached),
+                };
+                let start: u64 = epoch.start_number.into();
+                let length: u64 = epoch.length.into();
+                if start + length - 1 > self.safe_number {
+                    return Ok(Validity::Uncached);
+                }
+                Ok(match self.live_hash(start)? {
+                    Some(hash) => Validity::WhileBlock(start, hash),
+                    None => Validity::Uncached,
+                })
+            },
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::{MockChain, MockChainConfig, MockTransport};
+    use crate::rpc::RpcClient;
+    use ckb_types::bytes::Bytes;
+    use std::env;
+    use std::process;
+    use std::sync::{Arc, RwLock};
+
+    fn cached(chain: &Arc<RwLock<MockChain>>, dir: &Path) -> CachedSource {
+        let transport = MockTransport {
+            chain: Arc::clone(chain),
+        };
+        let client = RpcClient::with_transport("mock://chain", Box::new(transport));
+        CachedSource::open(Box::new(client), dir.to_str().unwrap(), 20).unwrap()
+    }
+
+    fn block_hash(source: &CachedSource, number: u64) -> H256 {
+        source
+            .get_block_by_number(number.into())
+            .unwrap()
+            .unwrap()
+            .header
+            .hash
+    }
+
+    #[test]
+    fn test_cache_until_reorg() {
+        let dir = env::temp_dir().join(format!("ckb-gbg-cache-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let chain = Arc::new(RwLock::new(MockChain::new(MockChainConfig::default())));
+        let tip = chain.read().unwrap().blocks.len() as u64 - 1;
+        let hash = chain.read().unwrap().blocks[50].header.hash.clone();
+        let reward = |source: &CachedSource| {
+            source
+                .get_cellbase_output_capacity_details(hash.clone())
+                .unwrap()
+                .unwrap()
+                .total
+        };
+
+        let source = cached(&chain, &dir);
+        assert_eq!(block_hash(&source, 50), hash);
+        let total = reward(&source);
+        // too close to the tip to be cached
+        block_hash(&source, tip);
+        let entries = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(entries, 2);
+
+        // the reward details now come from the cache
+        for reward in chain.write().unwrap().rewards.values_mut() {
+            reward.total = 0u64.into();
+        }
+        let source = cached(&chain, &dir);
+        assert_eq!(reward(&source), total);
+        assert_eq!(block_hash(&source, 50), hash);
+
+        // a chain with other block hashes replaces the cached block
+        *chain.write().unwrap() = MockChain::new(MockChainConfig {
+            miners: vec![Bytes::from(vec![7u8; 20]), Bytes::from(vec![8u8; 20])],
+            ..Default::default()
+        });
+        let reorg_hash = chain.read().unwrap().blocks[50].header.hash.clone();
+        assert_ne!(reorg_hash, hash);
+        let source = cached(&chain, &dir);
+        assert_eq!(block_hash(&source, 50), reorg_hash);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
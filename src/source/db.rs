@@ -0,0 +1,310 @@
+use super::ChainSource;
+use crate::explorer::{pays_reward, MAX_FINALIZATION_DELAY};
+use ckb_db::{DBConfig, RocksDB};
+use ckb_jsonrpc_types::{BlockNumber, BlockReward, BlockView, EpochNumber, EpochView, HeaderView};
+use ckb_store::{ChainDB, ChainStore, StoreConfig, COLUMNS};
+use ckb_types::{core::EpochExt, packed::Byte32, prelude::*, H256};
+use failure::{format_err, Error};
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+static SNAPSHOTS: AtomicUsize = AtomicUsize::new(0);
+
+/// The database of a stopped node, read directly instead of through JSON-RPC.
+///
+/// RocksDB writes to the directory it opens, so the store is opened on a
+/// snapshot in the temporary directory and `path` is never modified. The
+/// reward details are built like `get_cellbase_output_capacity_details`, the
+/// primary reward comes from the stored epoch of the rewarded block.
+pub struct DbSource {
+    path: String,
+    // dropped before the snapshot it reads
+    store: ChainDB,
+    _snapshot: Snapshot,
+    finalization_delay: u64,
+}
+
+/// A copy of a RocksDB directory, removed when dropped. The table files are
+/// hard linked when possible, RocksDB never modifies them in place.
+struct Snapshot {
+    dir: PathBuf,
+}
+
+impl Snapshot {
+    fn create(path: &Path) -> Result<Snapshot, Error> {
+        if !path.join("CURRENT").is_file() {
+            return Err(format_err!("{} is not a RocksDB directory", path.display()));
+        }
+        let dir = env::temp_dir().join(format!(
+            "ckb-gbg-db-{}-{}",
+            process::id(),
+            SNAPSHOTS.fetch_add(1, Ordering::SeqCst)
+        ));
+        if dir.exists() {
+            fs::remove_dir_all(&dir)?;
+        }
+        fs::create_dir_all(&dir)?;
+        let snapshot = Snapshot { dir };
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            // the lock of the node, which may still hold it
+            if name == "LOCK" || !entry.file_type()?.is_file() {
+                continue;
+            }
+            let target = snapshot.dir.join(&name);
+            let is_table = Path::new(&name)
+                .extension()
+                .map_or(false, |ext| ext == "sst");
+            if !is_table || fs::hard_link(entry.path(), &target).is_err() {
+                fs::copy(entry.path(), &target)
+                    .map_err(|e| format_err!("{}: {}", entry.path().display(), e))?;
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+impl DbSource {
+    pub fn open(path: &str) -> Result<DbSource, Error> {
+        let snapshot = Snapshot::create(Path::new(path))?;
+        let config = DBConfig {
+            path: snapshot.dir.clone(),
+            options: None,
+        };
+        let store = ChainDB::new(RocksDB::open(&config, COLUMNS), StoreConfig::default());
+        let finalization_delay = (1..=(MAX_FINALIZATION_DELAY + 1))
+            .find(|number| {
+                store
+                    .get_block_hash(*number)
+                    .and_then(|hash| store.get_block(&hash))
+                    .map_or(false, |block| pays_reward(&block))
+            })
+            .map(|number| number - 1)
+            .ok_or_else(|| format_err!("{} has no cellbase with outputs", path))?;
+        Ok(DbSource {
+            path: path.to_string(),
+            store,
+            _snapshot: snapshot,
+            finalization_delay,
+        })
+    }
+}
+
+fn epoch_view(ext: &EpochExt) -> EpochView {
+    EpochView {
+        number: ext.number().into(),
+        start_number: ext.start_number().into(),
+        length: ext.length().into(),
+        compact_target: ext.compact_target().into(),
+    }
+}
+
+impl ChainSource for DbSource {
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn get_block_by_number(&self, number: BlockNumber) -> Result<Option<BlockView>, Error> {
+        Ok(self
+            .store
+            .get_block_hash(number.into())
+            .and_then(|hash| self.store.get_block(&hash))
+            .map(Into::into))
+    }
+
+    fn get_header_by_number(&self, number: BlockNumber) -> Result<Option<HeaderView>, Error> {
+        Ok(self
+            .store
+            .get_block_hash(number.into())
+            .and_then(|hash| self.store.get_block_header(&hash))
+            .map(Into::into))
+    }
+
+    fn get_block_hash(&self, number: BlockNumber) -> Result<Option<H256>, Error> {
+        Ok(self
+            .store
+            .get_block_hash(number.into())
+            .map(|hash| hash.unpack()))
+    }
+
+    fn get_cellbase_output_capacity_details(
+        &self,
+        hash: H256,
+    ) -> Result<Option<BlockReward>, Error> {
+        let hash: Byte32 = hash.pack();
+        let number = match self.store.get_block_number(&hash) {
+            Some(number) if number > self.finalization_delay => number,
+            _ => return Ok(None),
+        };
+        let rewarded = number - self.finalization_delay;
+        let epoch = match self
+            .store
+            .get_block_hash(rewarded)
+            .and_then(|rewarded_hash| self.store.get_block_epoch(&rewarded_hash))
+        {
+            Some(epoch) => epoch,
+            None => return Ok(None),
+        };
+        let primary = epoch
+            .block_reward(rewarded)
+            .map_err(|e| format_err!("block {} reward: {}", rewarded, e))?
+            .as_u64();
+        let total = match self.store.get_cellbase(&hash) {
+            Some(cellbase) => cellbase.outputs_capacity()?.as_u64(),
+            None => return Ok(None),
+        };
+        Ok(Some(BlockReward {
+            total: total.into(),
+            primary: primary.into(),
+            secondary: total.saturating_sub(primary).into(),
+            tx_fee: 0u64.into(),
+            proposal_reward: 0u64.into(),
+        }))
+    }
+
+    fn get_tip_header(&self) -> Result<HeaderView, Error> {
+        self.store
+            .get_tip_header()
+            .map(Into::into)
+            .ok_or_else(|| format_err!("{} has no tip", self.path))
+    }
+
+    fn get_epoch_by_number(&self, number: EpochNumber) -> Result<Option<EpochView>, Error> {
+        Ok(self
+            .store
+            .get_epoch_index(number.into())
+            .and_then(|index| self.store.get_epoch_ext(&index))
+            .map(|ext| epoch_view(&ext)))
+    }
+}
+
+/// Writes `chain` with the store of ckb v0.25.0-rc1, the version the db feature
+/// is built with, including the epoch of every block like the node does.
+#[cfg(test)]
+pub fn write_db(path: &Path, chain: &crate::rpc::mock::MockChain) {
+    use ckb_types::{core::Capacity, U256};
+
+    let config = DBConfig {
+        path: path.to_path_buf(),
+        options: None,
+    };
+    let store = ChainDB::new(RocksDB::open(&config, COLUMNS), StoreConfig::default());
+    let txn = store.begin_transaction();
+    for block in &chain.blocks {
+        txn.insert_block(block).unwrap();
+        txn.attach_block(block).unwrap();
+        txn.insert_tip_header(&block.header()).unwrap();
+    }
+    for epoch in &chain.epochs {
+        let number: u64 = epoch.number.into();
+        let start_number: u64 = epoch.start_number.into();
+        let length: u64 = epoch.length.into();
+        // epochs are keyed by the last block of the previous epoch
+        let hash = chain.blocks[start_number.saturating_sub(1) as usize].hash();
+        let ext = EpochExt::new_builder()
+            .number(number)
+            .base_block_reward(Capacity::shannons(chain.config.primary_reward))
+            .remainder_reward(Capacity::zero())
+            .previous_epoch_hash_rate(U256::zero())
+            .last_block_hash_in_previous_epoch(hash.clone())
+            .start_number(start_number)
+            .length(length)
+            .compact_target(epoch.compact_target.into())
+            .build();
+        txn.insert_epoch_ext(&hash, &ext).unwrap();
+        for block in chain
+            .blocks
+            .iter()
+            .skip(start_number as usize)
+            .take(length as usize)
+        {
+            txn.insert_block_epoch_index(&block.hash(), &hash).unwrap();
+        }
+        if chain.blocks.len() as u64 <= start_number + length {
+            txn.insert_current_epoch_ext(&ext).unwrap();
+        }
+    }
+    txn.commit().unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rpc::mock::{MockChain, MockChainConfig};
+
+    fn file_names(path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(path)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn test_read_snapshot() {
+        let chain = MockChain::new(MockChainConfig::default());
+        let path = env::temp_dir().join(format!("ckb-gbg-db-test-{}", process::id()));
+        let _ = fs::remove_dir_all(&path);
+        write_db(&path, &chain);
+        let before = file_names(&path);
+
+        let source = DbSource::open(path.to_str().unwrap()).unwrap();
+        assert_eq!(source.finalization_delay, 11);
+        let tip = chain.blocks.last().unwrap();
+        assert_eq!(source.get_tip_header().unwrap().hash, tip.hash().unpack());
+        for number in &[0u64, 1, 99, 110] {
+            let expected = &chain.blocks[*number as usize];
+            let block = source
+                .get_block_by_number((*number).into())
+                .unwrap()
+                .unwrap();
+            assert_eq!(block.header.hash, expected.hash().unpack());
+            assert_eq!(
+                source.get_block_hash((*number).into()).unwrap(),
+                Some(expected.hash().unpack())
+            );
+        }
+        for epoch in &chain.epochs {
+            let stored = source.get_epoch_by_number(epoch.number).unwrap().unwrap();
+            assert_eq!(
+                serde_json::to_value(stored).unwrap(),
+                serde_json::to_value(epoch).unwrap()
+            );
+        }
+        // block 99 pays the reward of block 88
+        let anchor = &chain.blocks[99];
+        let reward = source
+            .get_cellbase_output_capacity_details(anchor.hash().unpack())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(reward).unwrap(),
+            serde_json::to_value(&chain.rewards[&anchor.hash()]).unwrap()
+        );
+        drop(source);
+
+        assert_eq!(file_names(&path), before);
+        fs::remove_dir_all(&path).unwrap();
+    }
+
+    #[test]
+    fn test_reject_missing_db() {
+        let path = env::temp_dir().join(format!("ckb-gbg-no-db-{}", process::id()));
+        fs::create_dir_all(&path).unwrap();
+        let err = DbSource::open(path.to_str().unwrap()).err().unwrap();
+        assert!(err.to_string().contains("is not a RocksDB directory"));
+        assert!(file_names(&path).is_empty());
+        fs::remove_dir_all(&path).unwrap();
+    }
+}
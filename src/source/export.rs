@@ -0,0 +1,174 @@
+use super::ChainSource;
+use crate::explorer::{pays_reward, primary_block_reward};
+use ckb_jsonrpc_types::{BlockNumber, BlockReward, BlockView, EpochNumber, EpochView, HeaderView};
+use ckb_types::{
+    core::{self, HeaderView as CoreHeaderView},
+    prelude::*,
+    H256,
+};
+use failure::{format_err, Error};
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+
+/// A chain exported by `ckb export`, one JSON block per line in ascending order.
+///
+/// Epochs are rebuilt from the block headers. The export has no reward details,
+/// the primary reward is recomputed from the issuance schedule and the total is
+/// the capacity of the cellbase outputs.
+pub struct ExportSource {
+    path: String,
+    genesis_hash: H256,
+    /// `blocks[i]` is block `first + i`.
+    blocks: Vec<BlockView>,
+    first: u64,
+    numbers: HashMap<H256, u64>,
+    epochs: BTreeMap<u64, EpochView>,
+    finalization_delay: u64,
+}
+
+impl ExportSource {
+    pub fn open(path: &str) -> Result<ExportSource, Error> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut blocks: Vec<BlockView> = Vec::new();
+        for (index, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let block: BlockView = serde_json::from_str(&line)
+                .map_err(|e| format_err!("{}:{}: {}", path, index + 1, e))?;
+            if let Some(parent) = blocks.last() {
+                let number = header_of(&block).number();
+                let parent_number = header_of(parent).number();
+                if number != parent_number + 1 {
+                    return Err(format_err!(
+                        "{}:{}: block {} does not follow block {}",
+                        path,
+                        index + 1,
+                        number,
+                        parent_number
+                    ));
+                }
+            }
+            blocks.push(block);
+        }
+
+        // the genesis block may be left out, block 1 still links to it
+        let first_block = blocks
+            .first()
+            .ok_or_else(|| format_err!("{} has no blocks", path))?;
+        let first_header = header_of(first_block);
+        let first = first_header.number();
+        let genesis_hash = match first {
+            0 => first_header.hash().unpack(),
+            1 => first_header.parent_hash().unpack(),
+            _ => return Err(format_err!("{} starts at block {}", path, first)),
+        };
+
+        let mut numbers = HashMap::with_capacity(blocks.len());
+        let mut epochs = BTreeMap::new();
+        let mut finalization_delay = None;
+        for block in &blocks {
+            let header = header_of(block);
+            let number = header.number();
+            numbers.insert(header.hash().unpack(), number);
+
+            let epoch = header.epoch();
+            epochs.entry(epoch.number()).or_insert_with(|| EpochView {
+                number: epoch.number().into(),
+                start_number: (number - epoch.index()).into(),
+                length: epoch.length().into(),
+                compact_target: header.compact_target().into(),
+            });
+
+            if finalization_delay.is_none() && number > 0 {
+                let view: core::BlockView = block.clone().into();
+                if pays_reward(&view) {
+                    finalization_delay = Some(number - 1);
+                }
+            }
+        }
+
+        Ok(ExportSource {
+            path: path.to_string(),
+            genesis_hash,
+            first,
+            numbers,
+            epochs,
+            finalization_delay: finalization_delay
+                .ok_or_else(|| format_err!("{} has no cellbase with outputs", path))?,
+            blocks,
+        })
+    }
+
+    fn block(&self, number: u64) -> Option<&BlockView> {
+        number
+            .checked_sub(self.first)
+            .and_then(|index| self.blocks.get(index as usize))
+    }
+}
+
+impl ChainSource for ExportSource {
+    fn name(&self) -> String {
+        self.path.clone()
+    }
+
+    fn get_block_by_number(&self, number: BlockNumber) -> Result<Option<BlockView>, Error> {
+        Ok(self.block(number.into()).cloned())
+    }
+
+    fn get_header_by_number(&self, number: BlockNumber) -> Result<Option<HeaderView>, Error> {
+        Ok(self.block(number.into()).map(|block| block.header.clone()))
+    }
+
+    fn get_block_hash(&self, number: BlockNumber) -> Result<Option<H256>, Error> {
+        let number: u64 = number.into();
+        if number == 0 {
+            return Ok(Some(self.genesis_hash.clone()));
+        }
+        Ok(self.block(number).map(|block| block.header.hash.clone()))
+    }
+
+    fn get_cellbase_output_capacity_details(
+        &self,
+        hash: H256,
+    ) -> Result<Option<BlockReward>, Error> {
+        let number = match self.numbers.get(&hash) {
+            Some(number) if *number > self.finalization_delay => *number,
+            _ => return Ok(None),
+        };
+        let rewarded = match self.block(number - self.finalization_delay) {
+            Some(rewarded) => rewarded,
+            None => return Ok(None),
+        };
+        let epoch = header_of(rewarded).epoch();
+        let primary = primary_block_reward(epoch.number(), epoch.index(), epoch.length());
+
+        let block: core::BlockView = self.block(number).expect("indexed block").clone().into();
+        let total = match block.transactions().first() {
+            Some(cellbase) => cellbase.outputs_capacity()?.as_u64(),
+            None => 0,
+        };
+        Ok(Some(BlockReward {
+            total: total.into(),
+            primary: primary.into(),
+            secondary: total.saturating_sub(primary).into(),
+            tx_fee: 0u64.into(),
+            proposal_reward: 0u64.into(),
+        }))
+    }
+
+    fn get_tip_header(&self) -> Result<HeaderView, Error> {
+        Ok(self.blocks.last().expect("non-empty export").header.clone())
+    }
+
+    fn get_epoch_by_number(&self, number: EpochNumber) -> Result<Option<EpochView>, Error> {
+        let number: u64 = number.into();
+        Ok(self.epochs.get(&number).cloned())
+    }
+}
+
+fn header_of(block: &BlockView) -> CoreHeaderView {
+    block.header.clone().into()
+}